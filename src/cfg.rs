@@ -1,51 +1,109 @@
-use std::{sync::Arc, io::{stdout, Write, ErrorKind, stderr}, collections::BTreeMap};
+use std::{sync::{Arc, OnceLock, RwLock}, io::{stdout, Write, ErrorKind, stderr}, collections::BTreeMap, net::IpAddr, time::Duration};
 
-use crate::{registerable::{self, DebugFmt, Route, ParseOptions, DebugSetting}};
+use crate::{internal::{RateLimiter, Registry, Spawner, Topics}, metrics::Metrics, registerable::{self, DebugFmt, Route, ParseOptions, DebugSetting, MatchPolicy, Captures, Level, Field, Record, Redaction}, exception::RouteError};
 
 pub struct Debug {
-    state: DebugSetting,
-    w: Box<dyn registerable::DebugFmt>
+    min: Option<Level>,
+    /// Coarse setting last chosen, kept apart from the level as both coarse settings write every record.
+    setting: DebugSetting,
+    w: Box<dyn registerable::DebugFmt>,
+    sink: Box<dyn registerable::DebugSink>
 }
 
 impl Debug {
 
-    pub fn is_state(&self, other: DebugSetting) -> bool { self.state >= other }
+    pub fn is_state(&self, other: DebugSetting) -> bool { self.setting >= other }
 
-    pub fn new(w: Box<dyn registerable::DebugFmt>) -> Debug { Debug{ state: DebugSetting::Standard, w } }
+    pub fn new(w: Box<dyn registerable::DebugFmt>) -> Debug { 
+        Debug{ min: Some(Level::Trace), setting: DebugSetting::Standard, w, sink: Box::new(StdSink) } 
+    }
 
     pub fn replace_writer(&mut self, w: Box<dyn registerable::DebugFmt>) { self.w = w }
+    pub fn replace_sink(&mut self, sink: Box<dyn registerable::DebugSink>) { self.sink = sink }
     
-    pub fn off(&mut self) { self.state = DebugSetting::None; }
-    pub fn standard(&mut self) { self.state = DebugSetting::Standard }
-    pub fn error(&mut self) { self.state = DebugSetting::Error }
-
-    pub fn get_setting(&self) -> DebugSetting { self.state.clone() }
-
-    pub fn write(&self, origin: &str, message: &str) {
-        if self.state > DebugSetting::None {
-            match stdout().write_all(self.w.debug(origin, message).as_bytes()) {
-                Ok(_) => (),
-                Err(err) => match err.kind() {
-                    ErrorKind::Interrupted => (),
-                    _other => self.write_err("cfg::Debug::write", &format!("IO Error: {}", &err)),
-                }
-            };
-        }
+    pub fn off(&mut self) { self.min = None; self.setting = DebugSetting::None; }
+    pub fn standard(&mut self) { self.min = Some(Level::Trace); self.setting = DebugSetting::Standard; }
+    pub fn coarse_error(&mut self) { self.min = Some(Level::Trace); self.setting = DebugSetting::Error; }
+    pub fn level(&mut self, level: Level) { self.min = Some(level); self.setting = DebugSetting::Standard; }
+
+    pub fn get_level(&self) -> Option<Level> { self.min }
+    pub fn get_setting(&self) -> DebugSetting { self.setting.clone() }
+
+    /// Checks if records of the given level are written.
+    pub fn enabled(&self, level: Level) -> bool {
+        self.min.is_some_and(|min| level >= min)
     }
 
-    pub fn write_err(&self, origin: &str, message: &str) {
-        if self.state > DebugSetting::Standard {
-            match stderr().write_all(self.w.debug(origin, message).as_bytes()) {
-                Ok(_) => (),
-                Err(err) => match err.kind() {
-                    ErrorKind::Interrupted => (),
-                    _other => self.write_err("cfg::Debug::write", &format!("IO Error: {}", &err)),
-                }
-            };        
+    pub fn log(&self, level: Level, origin: &str, message: &str, fields: &[Field]) {
+        if !self.enabled(level) { return; }
+
+        let record = Record{ level, origin, message, fields };
+        self.sink.write(&record, &self.w.format(&record));
+    }
+
+    pub fn trace(&self, origin: &str, message: &str, fields: &[Field]) { self.log(Level::Trace, origin, message, fields) }
+    pub fn debug(&self, origin: &str, message: &str, fields: &[Field]) { self.log(Level::Debug, origin, message, fields) }
+    pub fn info(&self, origin: &str, message: &str, fields: &[Field]) { self.log(Level::Info, origin, message, fields) }
+    pub fn warn(&self, origin: &str, message: &str, fields: &[Field]) { self.log(Level::Warn, origin, message, fields) }
+    pub fn error(&self, origin: &str, message: &str, fields: &[Field]) { self.log(Level::Error, origin, message, fields) }
+}
+
+/// Writes warnings and errors to stderr, and anything else to stdout, one entry per line.
+pub struct StdSink;
+
+impl registerable::DebugSink for StdSink {
+    fn write(&self, record: &Record, entry: &str) {
+        let result = if record.level >= Level::Warn {
+            writeln!(stderr(), "{}", entry)
+        } else {
+            writeln!(stdout(), "{}", entry)
+        };
+
+        // Nowhere left to report a failure to write to the standard streams.
+        if let Err(err) = result {
+            if err.kind() != ErrorKind::Interrupted { eprintln!("cfg::StdSink::write: IO Error: {}", err); }
         }
     }
 }
 
+/// Access log, kept apart from the debugger. Nothing is written unless a sink is set.
+pub struct AccessLog {
+    w: Box<dyn registerable::AccessFmt>,
+    sink: Option<Box<dyn registerable::AccessSink>>
+}
+
+impl AccessLog {
+    pub fn new(w: Box<dyn registerable::AccessFmt>) -> AccessLog { AccessLog{ w, sink: None } }
+
+    pub fn replace_writer(&mut self, w: Box<dyn registerable::AccessFmt>) { self.w = w }
+    pub fn replace_sink(&mut self, sink: Box<dyn registerable::AccessSink>) { self.sink = Some(sink) }
+
+    pub fn is_on(&self) -> bool { self.sink.is_some() }
+
+    pub fn write(&self, access: &registerable::Access) {
+        if let Some(sink) = &self.sink { sink.write(&self.w.format(access)); }
+    }
+}
+
+/// Formats access log entries as space separated `key=value` pairs, led by the timestamp.
+pub struct DefaultAccessFmt;
+
+impl registerable::AccessFmt for DefaultAccessFmt {
+    fn format(&self, access: &registerable::Access) -> String {
+        format!(
+            "{} peer={} ordern={} route={} request_bytes={} response_bytes={} latency_us={} outcome={}",
+            crate::debug::timestamp(access.time),
+            access.peer,
+            access.ordern,
+            access.route.map_or(String::from("-"), |route| route.to_string()),
+            access.request_bytes,
+            access.response_bytes,
+            access.latency.as_micros(),
+            access.outcome
+        )
+    }
+}
+
 pub struct DefaultDebugger;
 
 impl DebugFmt for DefaultDebugger {
@@ -59,7 +117,131 @@ impl DebugFmt for DefaultDebugger {
 }
 
 pub type ConfigAlias = Arc<Config>;
-pub type RouteMap = BTreeMap<Route, Box<dyn registerable::Controller>>;
+pub type SharedController = Arc<dyn registerable::Controller>;
+pub type RouteMap = BTreeMap<Route, SharedController>;
+
+/// The controller matched to a request, along with the route it was registered to.
+pub struct Found {
+    pub route: Route,
+    pub controller: SharedController,
+    /// Named captures, only for a `Route::Regex` match.
+    pub captures: Option<Captures>,
+    pub redaction: Redaction
+}
+
+/// The route map along with the compiled patterns of every `Route::Regex`
+/// and the redaction of each route, kept together so all are swapped under the same lock.
+#[derive(Default)]
+pub struct Routes {
+    pub rm: RouteMap,
+    pub redactions: BTreeMap<Route, Redaction>,
+    #[cfg(feature = "regex")]
+    pub rx: Vec<(regex::Regex, Route)>
+}
+
+impl Config {
+    /// Checks if the path is reserved for a route handled by bunker.
+    pub fn is_reserved(&self, path: &Route) -> bool {
+        [&self.help, &self.health, &self.subscribe, &self.unsubscribe].iter().any(|reserved| reserved.as_ref() == Some(path))
+    }
+}
+
+impl Routes {
+    /// Inserts the controller, returning the controller previously registered to the route.
+    pub fn insert(&mut self, route: Route, controller: SharedController) -> Result<Option<SharedController>, RouteError> {
+        #[cfg(feature = "regex")]
+        if let Route::Regex(pattern) = &route {
+            if !self.rx.iter().any(|(_, r)| r == &route) {
+                let rx = regex::Regex::new(pattern)
+                    .map_err(|err| RouteError::InvalidPattern(err.to_string()))?;
+                self.rx.push((rx, route.clone()));
+            }
+        }
+
+        Ok(self.rm.insert(route, controller))
+    }
+
+    /// Removes the controller registered to the route, along with its redaction, 
+    /// so a controller registered to the route later does not inherit it.
+    pub fn remove(&mut self, route: &Route) -> Option<SharedController> {
+        #[cfg(feature = "regex")]
+        self.rx.retain(|(_, r)| r != route);
+
+        self.redactions.remove(route);
+
+        self.rm.remove(route)
+    }
+
+    /// Lists every registered route along with its controller.
+    pub fn list(&self) -> Vec<registerable::RouteInfo> {
+        self.rm.iter()
+            .map(|(route, controller)| registerable::RouteInfo {
+                route: route.clone(),
+                controller: controller.type_name(),
+                description: controller.description()
+            })
+            .collect()
+    }
+
+    /// Finds the controller for a parsed path. Literal paths are tried first, then any `Route::Regex`
+    /// against the whole request, and finally `Route::NotFound`.
+    pub fn find(&self, path: &Route, subject: &str) -> Option<Found> {
+        let (route, controller, captures) = if let Some(controller) = self.rm.get(path) {
+            (path.clone(), controller, None)
+        } else if let Some((route, controller, captures)) = self.match_pattern(subject) {
+            (route.clone(), controller, Some(captures))
+        } else {
+            (Route::NotFound, self.rm.get(&Route::NotFound)?, None)
+        };
+
+        Some(Found {
+            redaction: self.redaction(&route),
+            controller: Arc::clone(controller),
+            route,
+            captures
+        })
+    }
+
+    /// Returns the redaction configured for the route.
+    pub fn redaction(&self, route: &Route) -> Redaction {
+        self.redactions.get(route).cloned().unwrap_or_default()
+    }
+
+    /// Finds the first `Route::Regex` matching the request, returning its controller along with the named captures.
+    #[cfg(feature = "regex")]
+    fn match_pattern(&self, subject: &str) -> Option<(&Route, &SharedController, Captures)> {
+        self.rx.iter().find_map(|(rx, route)| {
+            let caps = rx.captures(subject)?;
+            let controller = self.rm.get(route)?;
+
+            let captures = rx.capture_names()
+                .flatten()
+                .filter_map(|name| caps.name(name).map(|m| (name.to_string(), m.as_str().to_string())))
+                .collect();
+
+            Some((route, controller, captures))
+        })
+    }
+
+    #[cfg(not(feature = "regex"))]
+    fn match_pattern(&self, _subject: &str) -> Option<(&Route, &SharedController, Captures)> { None }
+}
+
+/// Blocks of addresses allowed or denied to connect. Denied blocks take precedence,
+/// and if any block is allowed, addresses outside every allowed block are denied.
+#[derive(Default, Clone)]
+pub struct Filter {
+    pub allow: Vec<registerable::Cidr>,
+    pub deny: Vec<registerable::Cidr>
+}
+
+impl Filter {
+    pub fn permits(&self, addr: IpAddr) -> bool {
+        if self.deny.iter().any(|cidr| cidr.contains(addr)) { return false; }
+
+        self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(addr))
+    }
+}
 
 pub struct Config {
     pub port: u16,
@@ -69,7 +251,38 @@ pub struct Config {
     pub endconn_msg: String,
     pub parse_options: ParseOptions,
     pub debug: Debug,
-    pub rm: RouteMap,
+    pub access: AccessLog,
+    pub metrics: Arc<Metrics>,
+    pub metrics_port: Option<u16>,
+    pub routes: RwLock<Routes>,
+    pub mp: MatchPolicy,
+    pub quit: Option<String>,
+    pub help: Option<Route>,
+    pub health: Option<Route>,
+    pub subscribe: Option<Route>,
+    pub unsubscribe: Option<Route>,
     pub mrl: usize,
-    pub er: String
-}
\ No newline at end of file
+    pub er: String,
+    pub limiter: RateLimiter,
+    pub rl_response: String,
+    pub rl_drop: Option<u32>,
+    pub filter: RwLock<Filter>,
+    pub registry: Registry,
+    pub topics: Topics,
+    pub max_messages: Option<u32>,
+    pub delimiter: Option<String>,
+    pub concurrent_pipelining: bool,
+    pub id_separator: Option<char>,
+    pub request_tasks: bool,
+    #[cfg(feature = "mio")]
+    pub event_loop: bool,
+    pub max_in_flight: usize,
+    /// Attached once the threadpool is created.
+    pub spawner: OnceLock<Spawner>,
+    pub max_lifetime: Option<Duration>,
+    pub goodbye: String,
+    pub farewell: Option<String>,
+    pub half_close: bool,
+    pub close_ack: Option<Duration>,
+    pub write_timeout: Duration
+}
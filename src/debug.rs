@@ -0,0 +1,417 @@
+//! Built-in implementations of `registerable::DebugSink` and `registerable::DebugFmt`,
+//! along with those of `registerable::AccessSink` and `registerable::AccessFmt` for the access log.
+
+use std::{collections::VecDeque, fmt::{self, Write as _}, fs::{self, File, OpenOptions}, io::{self, Write}, path::{Path, PathBuf}, sync::{Arc, Mutex}, thread, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+#[allow(unused_imports)]
+use crate::registerable::{DebugFmt, DebugSink, Level, Record, AccessFmt, AccessSink, Access};
+
+/// Displays raw bytes as printable ASCII, escaping anything else as in `{:?}` of a byte string,
+/// so invalid UTF-8 and control characters from a client are logged without loss.
+pub(crate) struct Raw<'a>(pub &'a [u8]);
+
+impl fmt::Display for Raw<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{}", std::ascii::escape_default(*byte))?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats the time as RFC 3339 in UTC, with millisecond precision.
+pub(crate) fn timestamp(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let days = (secs / 86_400) as i64;
+
+    // Converts days since the epoch to a civil date (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", 
+        year, month, day, 
+        secs / 3600 % 24, secs / 60 % 60, secs % 60, 
+        since.subsec_millis()
+    )
+}
+
+/// Formats every record as a single JSON object, for log shippers expecting JSON lines.
+/// 
+/// Each object holds `timestamp` (RFC 3339, UTC), `level`, `origin`, `ordern` (if the record has one), 
+/// `thread`, `message`, and any remaining fields of the record under `fields`.
+pub struct JsonDebugger;
+
+impl JsonDebugger {
+    /// Writes the string as a quoted JSON string, escaping quotes, backslashes and control characters.
+    fn quote(out: &mut String, value: &str) {
+        out.push('"');
+        for c in value.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); },
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+    }
+
+    /// Numeric id of the current thread, taken from its debug representation.
+    fn thread_id() -> String {
+        format!("{:?}", thread::current().id())
+            .chars()
+            .filter(char::is_ascii_digit)
+            .collect()
+    }
+}
+
+impl DebugFmt for JsonDebugger {
+    fn debug(&self, origin: &str, message: &str) -> String {
+        self.format(&Record{ level: Level::Info, origin, message, fields: &[] })
+    }
+
+    fn debug_err(&self, origin: &str, message: &str) -> String {
+        self.format(&Record{ level: Level::Error, origin, message, fields: &[] })
+    }
+
+    fn format(&self, record: &Record) -> String {
+        let mut out = String::from("{\"timestamp\":");
+        JsonDebugger::quote(&mut out, &timestamp(SystemTime::now()));
+        out.push_str(",\"level\":");
+        JsonDebugger::quote(&mut out, &record.level.to_string());
+        out.push_str(",\"origin\":");
+        JsonDebugger::quote(&mut out, record.origin);
+
+        if let Some(ordern) = record.field("ordern") {
+            let _ = write!(out, ",\"ordern\":{}", ordern);
+        }
+
+        let _ = write!(out, ",\"thread\":{}", JsonDebugger::thread_id());
+        out.push_str(",\"message\":");
+        JsonDebugger::quote(&mut out, record.message);
+
+        let mut fields = record.fields.iter().filter(|(key, _)| *key != "ordern").peekable();
+        if fields.peek().is_some() {
+            out.push_str(",\"fields\":{");
+            for (n, (key, value)) in fields.enumerate() {
+                if n > 0 { out.push(','); }
+                JsonDebugger::quote(&mut out, key);
+                out.push(':');
+                JsonDebugger::quote(&mut out, &value.to_string());
+            }
+            out.push('}');
+        }
+
+        out.push('}');
+        out
+    }
+}
+
+/// Reports a failure to write an entry, as a sink has nowhere else to report it.
+fn report(origin: &str, err: io::Error) {
+    if err.kind() != io::ErrorKind::Interrupted { eprintln!("{}: IO Error: {}", origin, err); }
+}
+
+/// Writes every entry to stdout, one entry per line. 
+/// Can be used for both debug records and the access log.
+pub struct StdoutSink;
+
+impl StdoutSink {
+    fn write_line(&self, entry: &str) {
+        if let Err(err) = writeln!(io::stdout(), "{}", entry) {
+            report("debug::StdoutSink::write", err);
+        }
+    }
+}
+
+impl DebugSink for StdoutSink {
+    fn write(&self, _record: &Record, entry: &str) { self.write_line(entry) }
+}
+
+impl AccessSink for StdoutSink {
+    fn write(&self, line: &str) { self.write_line(line) }
+}
+
+/// Appends every entry to a file, one entry per line. 
+/// Can be used for both debug records and the access log.
+pub struct FileSink(Mutex<File>);
+
+impl FileSink {
+    /// Opens the file for appending, creating it if it does not exist.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<FileSink> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileSink(Mutex::new(file)))
+    }
+}
+
+impl FileSink {
+    fn write_line(&self, entry: &str) {
+        if let Err(err) = writeln!(self.0.lock().unwrap(), "{}", entry) {
+            report("debug::FileSink::write", err);
+        }
+    }
+}
+
+impl DebugSink for FileSink {
+    fn write(&self, _record: &Record, entry: &str) { self.write_line(entry) }
+}
+
+impl AccessSink for FileSink {
+    fn write(&self, line: &str) { self.write_line(line) }
+}
+
+struct RotatingFile {
+    file: File,
+    size: u64,
+    opened: SystemTime
+}
+
+/// Appends every entry to a file, one entry per line, rotating the file once it
+/// exceeds a size or an age. Rotated files are renamed with a numbered suffix, 
+/// `debug.log.1` being the most recent, and only the configured number are kept.
+/// 
+/// **DEFAULTS:**
+/// ```text
+/// max_size: None
+/// max_age: None
+/// keep: 5
+/// ```
+pub struct RotatingFileSink {
+    path: PathBuf,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    keep: usize,
+    current: Mutex<RotatingFile>
+}
+
+impl RotatingFileSink {
+    /// Opens the file for appending, creating it if it does not exist.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<RotatingFileSink> {
+        let path = path.as_ref().to_path_buf();
+        let current = Mutex::new(RotatingFileSink::open(&path)?);
+
+        Ok(RotatingFileSink{ path, max_size: None, max_age: None, keep: 5, current })
+    }
+
+    /// Rotates the file once writing an entry would exceed the given number of bytes.
+    pub fn max_size(self, max_size: u64) -> RotatingFileSink {
+        RotatingFileSink{ max_size: Some(max_size), ..self }
+    }
+
+    /// Rotates the file once it has been written to for longer than the given duration.
+    pub fn max_age(self, max_age: Duration) -> RotatingFileSink {
+        RotatingFileSink{ max_age: Some(max_age), ..self }
+    }
+
+    /// Sets how many rotated files are kept. Older files are removed.
+    pub fn keep(self, keep: usize) -> RotatingFileSink {
+        RotatingFileSink{ keep, ..self }
+    }
+
+    fn open(path: &Path) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile{ file, size, opened: SystemTime::now() })
+    }
+
+    fn numbered(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn should_rotate(&self, current: &RotatingFile, len: u64) -> bool {
+        let oversize = self.max_size.is_some_and(|max| current.size > 0 && current.size + len > max);
+        let expired = self.max_age.is_some_and(|max| current.opened.elapsed().is_ok_and(|age| age >= max));
+
+        oversize || expired
+    }
+
+    /// Shifts every rotated file up by one, then reopens an empty file at the path.
+    fn rotate(&self, current: &mut RotatingFile) -> io::Result<()> {
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.numbered(self.keep));
+            for n in (1..self.keep).rev() {
+                let from = self.numbered(n);
+                if from.exists() { fs::rename(from, self.numbered(n + 1))?; }
+            }
+            fs::rename(&self.path, self.numbered(1))?;
+        }
+
+        *current = RotatingFileSink::open(&self.path)?;
+        Ok(())
+    }
+}
+
+impl RotatingFileSink {
+    fn write_line(&self, entry: &str) {
+        let mut current = self.current.lock().unwrap();
+        let len = entry.len() as u64 + 1;
+
+        if self.should_rotate(&current, len) {
+            if let Err(err) = self.rotate(&mut current) { report("debug::RotatingFileSink::rotate", err); }
+        }
+
+        match writeln!(current.file, "{}", entry) {
+            Ok(_) => current.size += len,
+            Err(err) => report("debug::RotatingFileSink::write", err),
+        }
+    }
+}
+
+impl DebugSink for RotatingFileSink {
+    fn write(&self, _record: &Record, entry: &str) { self.write_line(entry) }
+}
+
+impl AccessSink for RotatingFileSink {
+    fn write(&self, line: &str) { self.write_line(line) }
+}
+
+/// Keeps the most recent entries in memory, discarding the oldest once full.
+/// 
+/// Clones share the same buffer, so a clone can be registered on the builder 
+/// while the original is kept to read entries, such as for asserting logs in tests.
+#[derive(Clone)]
+pub struct MemorySink {
+    capacity: usize,
+    entries: Arc<Mutex<VecDeque<String>>>
+}
+
+impl MemorySink {
+    pub fn new(capacity: usize) -> MemorySink {
+        MemorySink{ capacity, entries: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))) }
+    }
+
+    /// Returns a copy of the entries held, from oldest to newest.
+    pub fn entries(&self) -> Vec<String> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Checks if any entry held contains the given text.
+    pub fn contains(&self, text: &str) -> bool {
+        self.entries.lock().unwrap().iter().any(|entry| entry.contains(text))
+    }
+
+    pub fn clear(&self) { self.entries.lock().unwrap().clear() }
+}
+
+impl MemorySink {
+    fn write_line(&self, entry: &str) {
+        if self.capacity == 0 { return; }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity { entries.pop_front(); }
+        entries.push_back(entry.to_string());
+    }
+}
+
+impl DebugSink for MemorySink {
+    fn write(&self, _record: &Record, entry: &str) { self.write_line(entry) }
+}
+
+impl AccessSink for MemorySink {
+    fn write(&self, line: &str) { self.write_line(line) }
+}
+
+/// Forwards every record to the `log` facade, with the origin as the target.
+/// The entry is logged as formatted by the configured `DebugFmt`.
+#[cfg(feature = "log")]
+pub struct LogSink;
+
+#[cfg(feature = "log")]
+impl DebugSink for LogSink {
+    fn write(&self, record: &Record, entry: &str) {
+        let level = match record.level {
+            Level::Trace => log::Level::Trace,
+            Level::Debug => log::Level::Debug,
+            Level::Info => log::Level::Info,
+            Level::Warn => log::Level::Warn,
+            Level::Error => log::Level::Error,
+        };
+
+        log::log!(target: record.origin, level, "{}", entry);
+    }
+}
+
+/// Emits every record as a `tracing` event, with the origin and the message as event fields.
+/// The `ordern`, `peer`, `route` and `size` fields are recorded as event fields of their own, so subscribers can filter on them,
+/// and any other fields are joined into a single `fields` event field.
+#[cfg(feature = "tracing")]
+pub struct TracingSink;
+
+#[cfg(feature = "tracing")]
+impl TracingSink {
+    /// Keys of the fields recorded as event fields of their own.
+    const KEYS: [&'static str; 4] = ["ordern", "peer", "route", "size"];
+}
+
+#[cfg(feature = "tracing")]
+impl DebugSink for TracingSink {
+    fn write(&self, record: &Record, _entry: &str) {
+        use tracing::field::display;
+
+        let field = |key| record.field(key).map(display);
+        let others = record.fields.iter()
+            .filter(|(key, _)| !TracingSink::KEYS.contains(key))
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<String>>();
+        let others = (!others.is_empty()).then(|| display(others.join(" ")));
+
+        // Levels must be known at compile time for `tracing::event!`.
+        macro_rules! emit {
+            ($level:expr) => {
+                tracing::event!(target: "bunker", $level, 
+                    origin = record.origin, ordern = field("ordern"), peer = field("peer"), route = field("route"), size = field("size"), 
+                    fields = others, "{}", record.message)
+            };
+        }
+
+        match record.level {
+            Level::Trace => emit!(tracing::Level::TRACE),
+            Level::Debug => emit!(tracing::Level::DEBUG),
+            Level::Info => emit!(tracing::Level::INFO),
+            Level::Warn => emit!(tracing::Level::WARN),
+            Level::Error => emit!(tracing::Level::ERROR),
+        }
+    }
+}
+
+/// Formats every access log entry as a single JSON object, with the same keys as the fields of `registerable::Access`.
+/// The route is `null` if no controller matched, and the latency is given in microseconds as `latency_us`.
+pub struct JsonAccessFmt;
+
+impl AccessFmt for JsonAccessFmt {
+    fn format(&self, access: &Access) -> String {
+        let mut out = String::from("{\"timestamp\":");
+        JsonDebugger::quote(&mut out, &timestamp(access.time));
+        out.push_str(",\"peer\":");
+        JsonDebugger::quote(&mut out, access.peer);
+        let _ = write!(out, ",\"ordern\":{}", access.ordern);
+        out.push_str(",\"route\":");
+        match access.route {
+            Some(route) => JsonDebugger::quote(&mut out, &route.to_string()),
+            None => out.push_str("null"),
+        }
+        let _ = write!(
+            out, 
+            ",\"request_bytes\":{},\"response_bytes\":{},\"latency_us\":{},\"outcome\":\"{}\"}}",
+            access.request_bytes, access.response_bytes, access.latency.as_micros(), access.outcome
+        );
+        out
+    }
+}
@@ -1,10 +1,12 @@
 use std::{fmt::Display, io};
 
+use crate::registerable::Route;
+
 /// Error messages strictly related to bunker's execution.
 /// 
 /// **WARNING: BunkerError is being phased out for external use since 0.2**
 #[derive(Debug)]
-#[deprecated(since="0.2", note="\nnow has no application externally, and is being replaced internally")]
+#[deprecated(since="0.2.0", note="\nnow has no application externally, and is being replaced internally")]
 pub enum BunkerError {
     BadRequest(String),
     InvalidThreadPoolSize(usize),
@@ -52,4 +54,70 @@ impl Display for InternalError {
             InternalError::IO(err) => Display::fmt(err, f),
         }
     }
-}
\ No newline at end of file
+}
+
+/// Errors from changing the routes of a server.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RouteError {
+    /// A controller is already registered to the route.
+    Occupied,
+    /// No controller is registered to the route.
+    NotRegistered,
+    /// The pattern of a `Route::Regex` failed to compile.
+    InvalidPattern(String),
+    /// Every route registered more than once, when strict routes are set.
+    Duplicate(Vec<Route>)
+}
+
+impl Display for RouteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RouteError::Occupied => write!(f, "A controller is already registered to this route!"),
+            RouteError::NotRegistered => write!(f, "No controller is registered to this route!"),
+            RouteError::InvalidPattern(err) => write!(f, "Invalid pattern given for Route::Regex!\n{}", err),
+            RouteError::Duplicate(routes) => write!(f, 
+                "Routes registered more than once!\nConflicts: {}", 
+                routes.iter().map(|route| route.to_string()).collect::<Vec<String>>().join(", ")),
+        }
+    }
+}
+
+/// Errors from parsing a `registerable::Cidr`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseCidrError {
+    InvalidAddress(String),
+    InvalidPrefix(String)
+}
+
+impl Display for ParseCidrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseCidrError::InvalidAddress(addr) => write!(f, "Invalid IP address given for CIDR block: {}", addr),
+            ParseCidrError::InvalidPrefix(prefix) => write!(f, "Invalid prefix length given for CIDR block: {}", prefix),
+        }
+    }
+}
+
+/// Errors from pushing a message to a connected client.
+#[derive(Debug)]
+pub enum PushError {
+    /// No connection is open with the order number.
+    NotConnected(u64),
+    /// The message exceeds the max response length.
+    Oversize(usize),
+    IO(io::Error)
+}
+
+impl From<io::Error> for PushError { 
+    fn from(err: io::Error) -> PushError { PushError::IO(err) }
+}
+
+impl Display for PushError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PushError::NotConnected(order_number) => write!(f, "No connection is open with order number {}!", order_number),
+            PushError::Oversize(len) => write!(f, "Pushed message of {} bytes exceeds the max response length!", len),
+            PushError::IO(err) => Display::fmt(err, f),
+        }
+    }
+}
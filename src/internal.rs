@@ -1,26 +1,63 @@
-use std::{sync::{Arc, Mutex, mpsc}, thread};
+use std::{collections::{BTreeMap, BTreeSet}, io::{self, Write}, panic::{self, AssertUnwindSafe}, net::{IpAddr, Shutdown, TcpStream}, sync::{Arc, Mutex, MutexGuard, PoisonError, mpsc, atomic::{AtomicUsize, Ordering}}, thread, time::{Duration, Instant}};
+
+use crate::registerable::{RateKey, RateLimit, Route};
 
 type Task = Box<dyn FnOnce() + Send + 'static>;
 
+/// Utilization of a threadpool, shared with its workers.
+pub struct PoolStats {
+    size: usize,
+    queued: AtomicUsize,
+    busy: AtomicUsize
+}
+
+impl PoolStats {
+    pub fn size(&self) -> usize { self.size }
+    /// Tasks sent to the pool that no worker has picked up yet.
+    pub fn queued(&self) -> usize { self.queued.load(Ordering::Relaxed) }
+    /// Workers currently running a task.
+    pub fn busy(&self) -> usize { self.busy.load(Ordering::Relaxed) }
+}
+
 enum Order {
     Do(Task),
     Stop
 }
 
+/// Counts a worker as busy for as long as it is held, including while a panicking task unwinds.
+struct Busy<'a>(&'a PoolStats);
+
+impl Busy<'_> {
+    fn new(stats: &PoolStats) -> Busy<'_> {
+        stats.busy.fetch_add(1, Ordering::Relaxed);
+        Busy(stats)
+    }
+}
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) { self.0.busy.fetch_sub(1, Ordering::Relaxed); }
+}
+
 struct Worker {
     id: usize,
     thread: Option<thread::JoinHandle<()>>
 }
 
 impl Worker {
-    fn new(id: usize, cin: Arc<Mutex<mpsc::Receiver<Order>>>) -> Worker {
+    fn new(id: usize, cin: Arc<Mutex<mpsc::Receiver<Order>>>, stats: Arc<PoolStats>) -> Worker {
         let thread = Some(thread::spawn(move|| loop {
             let order = cin
                 .lock().unwrap()
                 .recv().unwrap();
 
             match order {
-                Order::Do(task) => task(),
+                Order::Do(task) => {
+                    stats.queued.fetch_sub(1, Ordering::Relaxed);
+                    let _busy = Busy::new(&stats);
+
+                    // A panicking task is left to unwind on its own, so the worker keeps taking orders.
+                    let _ = panic::catch_unwind(AssertUnwindSafe(task));
+                },
                 Order::Stop => break 
             };
         }));
@@ -31,6 +68,7 @@ impl Worker {
 
 pub struct Threadpool {
     size: usize,
+    stats: Arc<PoolStats>,
     cout: mpsc::Sender<Order>,
     workers: Vec<Worker>
 }
@@ -42,22 +80,29 @@ impl Threadpool {
         let (cout, cin) = mpsc::channel::<Order>();
         let cin = Arc::new(Mutex::new(cin));
 
+        let stats = Arc::new(PoolStats{ size, queued: AtomicUsize::new(0), busy: AtomicUsize::new(0) });
+
         let mut workers = Vec::<Worker>::with_capacity(size);
-        for id in 0..size { workers.push(Worker::new(id, Arc::clone(&cin))); }
+        for id in 0..size { workers.push(Worker::new(id, Arc::clone(&cin), Arc::clone(&stats))); }
 
-        Threadpool{size, cout, workers}
+        Threadpool{size, stats, cout, workers}
     }
 
     pub fn execute<F>(&self, f: F) 
         where
             F: FnOnce() + Send + 'static
     {
+        self.stats.queued.fetch_add(1, Ordering::Relaxed);
         self.cout
             .send(Order::Do(Box::new(f)))
             .unwrap();
     }
 
+    /// Returns a handle for sending tasks to the pool from other threads, including its own workers.
+    pub fn spawner(&self) -> Spawner { Spawner{ cout: self.cout.clone(), stats: Arc::clone(&self.stats) } }
+
     pub fn get_size(&self) -> usize { self.size }
+    pub fn get_stats(&self) -> Arc<PoolStats> { Arc::clone(&self.stats) }
     pub fn get_all_workerid(&self) -> Vec<usize> { 
         self.workers.iter()
             .map(|w| w.id)
@@ -65,6 +110,25 @@ impl Threadpool {
     }
 }
 
+/// Sends tasks to a threadpool without owning it.
+#[derive(Clone)]
+pub struct Spawner {
+    cout: mpsc::Sender<Order>,
+    stats: Arc<PoolStats>
+}
+
+impl Spawner {
+    pub fn execute<F>(&self, f: F) 
+        where
+            F: FnOnce() + Send + 'static
+    {
+        self.stats.queued.fetch_add(1, Ordering::Relaxed);
+        self.cout
+            .send(Order::Do(Box::new(f)))
+            .unwrap();
+    }
+}
+
 impl Drop for Threadpool {
     fn drop(&mut self) {
         self.workers
@@ -87,4 +151,282 @@ impl Drop for Threadpool {
         //     }
         // }
     }
-}
\ No newline at end of file
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant
+}
+
+/// Buckets are dropped once they have refilled, past this many.
+const MAX_IDLE_BUCKETS: usize = 4096;
+
+/// Least time between prunes, so a map of buckets that are all in use is not scanned on every request.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Index of the limit, along with the peer and/or route its bucket counts requests from.
+type BucketKey = (usize, Option<IpAddr>, Option<Route>);
+
+/// Token buckets of every rate limit.
+pub struct RateLimiter {
+    limits: Vec<RateLimit>,
+    buckets: Mutex<Buckets>
+}
+
+struct Buckets {
+    map: BTreeMap<BucketKey, Bucket>,
+    pruned: Instant
+}
+
+impl RateLimiter {
+    pub fn new(limits: Vec<RateLimit>) -> RateLimiter {
+        RateLimiter{ limits, buckets: Mutex::new(Buckets{ map: BTreeMap::new(), pruned: Instant::now() }) }
+    }
+
+    /// Takes a token from every bucket the request counts against, unless any of them is empty.
+    /// Returns false if the request is limited.
+    pub fn check(&self, peer: Option<IpAddr>, route: Option<&Route>) -> bool {
+        self.check_at(peer, route, Instant::now())
+    }
+
+    /// Same as `RateLimiter::check`, as of the given time.
+    pub fn check_at(&self, peer: Option<IpAddr>, route: Option<&Route>, now: Instant) -> bool {
+        if self.limits.is_empty() { return true; }
+
+        let mut guard = self.buckets.lock().unwrap();
+        let Buckets{ map: buckets, pruned } = &mut *guard;
+
+        let keys: Vec<_> = self.limits.iter().enumerate()
+            .filter(|(_, limit)| limit.route.is_none() || limit.route.as_ref() == route)
+            .map(|(n, limit)| match limit.key {
+                RateKey::Peer => (n, peer, None),
+                RateKey::Route => (n, None, route.cloned()),
+                RateKey::PeerAndRoute => (n, peer, route.cloned()),
+            })
+            .collect();
+
+        for key in &keys {
+            let limit = &self.limits[key.0];
+
+            let bucket = buckets.entry(key.clone()).or_insert(Bucket{ tokens: limit.burst as f64, refilled: now });
+            bucket.tokens = RateLimiter::refill(limit, bucket, now);
+            bucket.refilled = now;
+        }
+
+        let allowed = keys.iter().all(|key| buckets[key].tokens >= 1.0);
+        if allowed {
+            keys.iter().for_each(|key| buckets.get_mut(key).unwrap().tokens -= 1.0);
+        }
+
+        // Buckets are refilled as of now, since their tokens were last updated when their key was last checked.
+        if buckets.len() > MAX_IDLE_BUCKETS && now.saturating_duration_since(*pruned) >= PRUNE_INTERVAL {
+            let limits = &self.limits;
+            buckets.retain(|key, bucket| RateLimiter::refill(&limits[key.0], bucket, now) < limits[key.0].burst as f64);
+            *pruned = now;
+        }
+
+        allowed
+    }
+
+    /// Returns the tokens the bucket holds as of the given time, up to the burst of its limit.
+    fn refill(limit: &RateLimit, bucket: &Bucket, now: Instant) -> f64 {
+        let rate = limit.requests as f64 / limit.per.as_secs_f64().max(f64::EPSILON);
+        (bucket.tokens + now.duration_since(bucket.refilled).as_secs_f64() * rate).min(limit.burst as f64)
+    }
+
+    /// Number of buckets currently kept.
+    #[cfg(test)]
+    pub fn bucket_count(&self) -> usize { self.buckets.lock().unwrap().map.len() }
+}
+
+/// Writing half of a connection, shared between the worker serving it and anything pushing to it,
+/// so framed messages are never interleaved.
+#[derive(Clone)]
+pub struct SharedStream(Arc<Mutex<Out>>);
+
+impl SharedStream {
+    pub fn new(stream: TcpStream) -> SharedStream { SharedStream(Arc::new(Mutex::new(Out::Stream(stream)))) }
+
+    /// Creates a writing half that queues writes for the event loop owning the connection.
+    #[cfg(feature = "mio")]
+    pub fn queued(outbox: Outbox) -> SharedStream { SharedStream(Arc::new(Mutex::new(Out::Queue(outbox)))) }
+
+    /// Locks the stream for writing several frames in a row.
+    /// A lock poisoned by a panicking stream is recovered, as a partly written frame only fails its own connection.
+    pub fn lock(&self) -> MutexGuard<'_, Out> { self.0.lock().unwrap_or_else(PoisonError::into_inner) }
+
+    /// Writes the whole of an already framed message.
+    pub fn write(&self, bytes: &[u8]) -> io::Result<()> {
+        let mut stream = self.lock();
+        stream.write_all(bytes)?;
+        stream.flush()
+    }
+
+    /// Ends the connection after a failed write, which its reader then sees as closed.
+    /// Queued writes never block, so the event loop only ever drops a connection itself.
+    pub fn abort(&self) {
+        match &*self.lock() {
+            Out::Stream(stream) => { let _ = stream.shutdown(Shutdown::Both); },
+            #[cfg(feature = "mio")]
+            Out::Queue(_) => (),
+        }
+    }
+
+    /// Wakes the event loop owning the connection, if any, to check on its progress.
+    pub fn wake(&self) {
+        #[cfg(feature = "mio")]
+        if let Out::Queue(outbox) = &*self.lock() { outbox.send(Command::Wake); }
+    }
+}
+
+/// Where the writes of a connection go.
+pub enum Out {
+    /// Straight to the stream, blocking until written.
+    Stream(TcpStream),
+    /// To the event loop owning the stream, once flushed.
+    #[cfg(feature = "mio")]
+    Queue(Outbox)
+}
+
+impl Write for Out {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Out::Stream(stream) => stream.write(buf),
+            #[cfg(feature = "mio")]
+            Out::Queue(outbox) => {
+                outbox.pending.extend_from_slice(buf);
+                Ok(buf.len())
+            },
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Out::Stream(stream) => stream.flush(),
+            #[cfg(feature = "mio")]
+            Out::Queue(outbox) => outbox.flush(),
+        }
+    }
+}
+
+/// Instruction sent to the event loop on behalf of a connection.
+#[cfg(feature = "mio")]
+pub enum Command {
+    Write(Vec<u8>),
+    /// Checks on the progress of the connection.
+    Wake
+}
+
+/// Queues the writes of a connection owned by the event loop, sending them once flushed.
+#[cfg(feature = "mio")]
+pub struct Outbox {
+    ordern: u64,
+    pending: Vec<u8>,
+    tx: mpsc::Sender<(u64, Command)>,
+    waker: Arc<mio::Waker>
+}
+
+#[cfg(feature = "mio")]
+impl Outbox {
+    pub fn new(ordern: u64, tx: mpsc::Sender<(u64, Command)>, waker: Arc<mio::Waker>) -> Outbox {
+        Outbox{ ordern, pending: Vec::new(), tx, waker }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            let bytes = std::mem::take(&mut self.pending);
+            self.send(Command::Write(bytes));
+        }
+        Ok(())
+    }
+
+    /// Commands are dropped once the event loop has stopped.
+    fn send(&self, command: Command) {
+        if self.tx.send((self.ordern, command)).is_ok() { let _ = self.waker.wake(); }
+    }
+}
+
+/// Writing halves of every open connection, keyed by order number.
+#[derive(Default)]
+pub struct Registry {
+    streams: Mutex<BTreeMap<u64, SharedStream>>
+}
+
+impl Registry {
+    pub fn insert(&self, ordern: u64, stream: SharedStream) { self.streams.lock().unwrap().insert(ordern, stream); }
+    pub fn remove(&self, ordern: u64) { self.streams.lock().unwrap().remove(&ordern); }
+    pub fn get(&self, ordern: u64) -> Option<SharedStream> { self.streams.lock().unwrap().get(&ordern).cloned() }
+
+    /// Copies every stream, so writing to them does not hold up connections opening or closing.
+    pub fn all(&self) -> Vec<(u64, SharedStream)> {
+        self.streams.lock().unwrap().iter().map(|(ordern, stream)| (*ordern, stream.clone())).collect()
+    }
+}
+
+/// Order numbers of the connections subscribed to each topic.
+#[derive(Default)]
+pub struct Topics {
+    subs: Mutex<BTreeMap<String, BTreeSet<u64>>>
+}
+
+impl Topics {
+    /// Returns false if the connection was already subscribed.
+    pub fn subscribe(&self, topic: &str, ordern: u64) -> bool {
+        self.subs.lock().unwrap().entry(topic.to_string()).or_default().insert(ordern)
+    }
+
+    /// Returns false if the connection was not subscribed.
+    pub fn unsubscribe(&self, topic: &str, ordern: u64) -> bool {
+        let mut subs = self.subs.lock().unwrap();
+        let Some(orderns) = subs.get_mut(topic) else { return false };
+
+        let removed = orderns.remove(&ordern);
+        if orderns.is_empty() { subs.remove(topic); }
+        removed
+    }
+
+    /// Removes every subscription of a connection once it has closed.
+    pub fn remove(&self, ordern: u64) {
+        self.subs.lock().unwrap().retain(|_, orderns| { orderns.remove(&ordern); !orderns.is_empty() });
+    }
+
+    pub fn subscribers(&self, topic: &str) -> Vec<u64> {
+        self.subs.lock().unwrap().get(topic).map_or(Vec::new(), |orderns| orderns.iter().copied().collect())
+    }
+
+    /// Topics with at least one subscriber.
+    pub fn topics(&self) -> Vec<String> { self.subs.lock().unwrap().keys().cloned().collect() }
+}
+
+/// Splits the requests out of the bytes read from a connection, on a delimiter.
+pub struct Framer {
+    delimiter: Vec<u8>,
+    buffered: Vec<u8>,
+    /// Longest incomplete request kept, past which it is passed on as it is.
+    max: usize
+}
+
+impl Framer {
+    pub fn new(delimiter: &str, max: usize) -> Framer {
+        Framer{ delimiter: delimiter.as_bytes().to_vec(), buffered: Vec::new(), max }
+    }
+
+    /// Adds the bytes read, returning every request they complete without its delimiter, in order.
+    /// Empty requests are skipped.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        self.buffered.extend_from_slice(bytes);
+
+        let mut requests = Vec::new();
+        let mut start = 0;
+
+        while let Some(pos) = self.buffered[start..].windows(self.delimiter.len()).position(|window| window == self.delimiter) {
+            if pos > 0 { requests.push(self.buffered[start..start + pos].to_vec()); }
+            start += pos + self.delimiter.len();
+        }
+        self.buffered.drain(..start);
+
+        if self.buffered.len() > self.max { requests.push(std::mem::take(&mut self.buffered)); }
+
+        requests
+    }
+}
@@ -1,5 +1,7 @@
 pub mod server;
 pub mod exception;
+pub mod debug;
+pub mod metrics;
 #[allow(deprecated)]
 pub mod registerable;
 
@@ -8,13 +10,989 @@ mod cfg;
 
 #[cfg(test)]
 mod tests {
-    use crate::{registerable::ParseOptions, internal::Threadpool};
+    use std::{cell::RefCell, rc::Rc, sync::{Arc, Condvar, Mutex, atomic::{AtomicUsize, Ordering}}, net::{IpAddr, Shutdown, TcpStream}, io::{Read, Write}, thread, time::{Duration, Instant}};
+
+    use crate::{registerable::{ParseOptions, MatchPolicy, Route, Controller, DebugSink, Record, Level, Redaction, RateLimit, Cidr, Response, DebugSetting}, internal::{Threadpool, RateLimiter}, server::{Builder, Host, PushHandle}, exception::{RouteError, PushError}, cfg::{Debug, DefaultDebugger, Routes}, debug::{MemorySink, RotatingFileSink, JsonDebugger, Raw, timestamp}};
+    use crate::registerable::Outcome;
+
+    /// Runs the server on a separate thread, returning a connected client.
+    fn connect(port: u16, builder: Builder) -> TcpStream {
+        run(builder.port(port).build())
+    }
+
+    /// Runs an already built server on a separate thread, returning a connected client.
+    fn run(host: Host) -> TcpStream {
+        let port = host.get_port();
+        thread::spawn(move || host.run());
+
+        loop {
+            match TcpStream::connect(("127.0.0.1", port)) {
+                Ok(stream) => return stream,
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+    }
+
+    /// Sends a request, returning the response with its length prefix, or nothing if the connection was closed.
+    fn request(stream: &mut TcpStream, req: &str) -> String {
+        if stream.write_all(req.as_bytes()).is_err() { return String::new(); }
+
+        let mut buff = [0_u8; 1024];
+        let size = stream.read(&mut buff).unwrap_or(0);
+        String::from_utf8_lossy(&buff[..size]).to_string()
+    }
+
+    struct Echo;
+
+    impl Controller for Echo {
+        fn serve(&self, msg: String, _: Rc<RefCell<String>>) -> String { msg }
+    }
 
     #[test]
     fn parse_options_separators_check() {
         assert!(ParseOptions::separator(vec![]).is_separators())
     }
 
+    #[test]
+    fn match_policy_folds_and_trims() {
+        let policy = MatchPolicy::exact().case_insensitive().trimmed();
+
+        assert_eq!(policy.apply("Ping "), "ping");
+        assert_eq!(MatchPolicy::exact().apply("Ping "), "Ping ");
+        assert!(policy.apply_route(Route::NotFound) == Route::NotFound);
+    }
+
+    /// Replies with the named captures, sorted by name.
+    #[cfg(feature = "regex")]
+    struct Captured;
+
+    #[cfg(feature = "regex")]
+    impl Controller for Captured {
+        fn serve(&self, msg: String, _: Rc<RefCell<String>>) -> String { msg }
+
+        fn serve_captures(&self, _: String, captures: crate::registerable::Captures, _: Rc<RefCell<String>>) -> String {
+            let mut captures: Vec<String> = captures.into_iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+            captures.sort();
+            captures.join(",")
+        }
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn regex_routes_match_whole_request() {
+        let mut stream = connect(41127, Builder::new()
+            .debugger_level_none()
+            .register(Box::new(Echo), Route::Path("P".to_string()))
+            .register(Box::new(Captured), Route::Regex(r"^GET(?P<id>[0-9]{2}) (?P<arg>\w+)$".to_string()))
+            .register(Box::new(Captured), Route::Regex(r"^PUT(?P<id>[0-9]{2})".to_string()))
+            .register(Box::new(Echo), Route::NotFound));
+
+        // Patterns see the whole request, though the position splits off a single character.
+        assert_eq!(request(&mut stream, "GET42 abc"), "0013arg=abc,id=42");
+        assert_eq!(request(&mut stream, "GET42 abc\n"), "0013arg=abc,id=42");
+
+        // Literal routes are tried first, and unmatched requests fall back to `Route::NotFound`.
+        assert_eq!(request(&mut stream, "PUT07"), "0004UT07");
+        assert_eq!(request(&mut stream, "DEL01"), "0004EL01");
+    }
+
+    #[test]
+    fn route_handle_changes_routes() {
+        let host = Builder::new()
+            .match_policy(MatchPolicy::exact().case_insensitive())
+            .build();
+        let handle = host.route_handle();
+
+        assert_eq!(handle.add(Box::new(Echo), Route::Path("PING".to_string())), Ok(()));
+        assert!(handle.contains(Route::Path("ping".to_string())));
+        assert_eq!(handle.add(Box::new(Echo), Route::Path("ping".to_string())), Err(RouteError::Occupied));
+        assert_eq!(handle.replace(Box::new(Echo), Route::Path("ping".to_string())), Ok(true));
+        assert_eq!(handle.redact(Route::Path("pong".to_string()), Redaction::Hidden), Err(RouteError::NotRegistered));
+        assert_eq!(handle.redact(Route::Path("PING".to_string()), Redaction::Hidden), Ok(()));
+        assert!(handle.clear_redaction(Route::Path("ping".to_string())));
+        assert!(!handle.clear_redaction(Route::Path("ping".to_string())));
+        assert_eq!(handle.remove(Route::Path("Ping".to_string())), Ok(()));
+        assert_eq!(handle.remove(Route::Path("ping".to_string())), Err(RouteError::NotRegistered));
+    }
+
+    #[test]
+    fn routes_are_listed() {
+        let host = Builder::new()
+            .register(Box::new(Echo), Route::Path("echo".to_string()))
+            .build();
+        let routes: Vec<_> = host.routes().collect();
+
+        assert_eq!(routes.len(), 1);
+        assert!(routes[0].route == Route::Path("echo".to_string()));
+        assert!(routes[0].controller.ends_with("Echo"));
+        assert_eq!(routes[0].description, None);
+    }
+
+    #[test]
+    fn strict_routes_report_duplicates() {
+        let routes = |builder: Builder| builder
+            .register(Box::new(Echo), Route::Path("echo".to_string()))
+            .register(Box::new(Echo), Route::Path("ECHO".to_string()))
+            .replace(Box::new(Echo), Route::NotFound)
+            .replace(Box::new(Echo), Route::NotFound);
+
+        let policy = MatchPolicy::exact().case_insensitive();
+
+        // Without strict routes, the collision is only written to the debugger.
+        let sink = MemorySink::new(4);
+        assert!(routes(Builder::new().match_policy(policy.clone()).set_debug_sink(Box::new(sink.clone()))).try_build().is_ok());
+        assert_eq!(sink.entries().len(), 1);
+        assert!(sink.contains("Route registered more than once") && sink.contains("ECHO"));
+        assert!(routes(Builder::new().strict_routes()).try_build().is_ok());
+        assert_eq!(
+            routes(Builder::new().match_policy(policy).strict_routes()).try_build().err(),
+            Some(RouteError::Duplicate(vec![Route::Path("echo".to_string())]))
+        );
+    }
+
+    #[test]
+    fn debug_filters_by_level() {
+        struct Capture(Arc<Mutex<Vec<String>>>);
+
+        impl DebugSink for Capture {
+            fn write(&self, record: &Record, entry: &str) {
+                assert_eq!(record.field("ordern").map(|v| v.to_string()), Some("7".to_string()));
+                self.0.lock().unwrap().push(entry.to_string());
+            }
+        }
+
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let mut debug = Debug::new(Box::new(DefaultDebugger));
+        debug.replace_sink(Box::new(Capture(Arc::clone(&entries))));
+        debug.level(Level::Info);
+
+        debug.debug("origin", "hidden", &[("ordern", &7)]);
+        debug.error("origin", "shown", &[("ordern", &7)]);
+
+        assert_eq!(*entries.lock().unwrap(), vec!["origin:\nERROR! shown ordern=7".to_string()]);
+
+        // The coarse error setting from 0.2 still writes every record.
+        debug.coarse_error();
+        debug.trace("origin", "traced", &[("ordern", &7)]);
+        assert_eq!(entries.lock().unwrap().len(), 2);
+        assert!(debug.get_setting() == DebugSetting::Error && debug.is_state(DebugSetting::Standard));
+    }
+
+    #[test]
+    fn memory_sink_keeps_latest() {
+        let sink = MemorySink::new(2);
+        let mut debug = Debug::new(Box::new(DefaultDebugger));
+        debug.replace_sink(Box::new(sink.clone()));
+
+        for n in 0..3 { debug.info("origin", &n.to_string(), &[]); }
+
+        assert_eq!(sink.entries(), vec!["origin:\n1".to_string(), "origin:\n2".to_string()]);
+    }
+
+    #[test]
+    fn rotating_file_sink_rotates_by_size() {
+        let dir = std::env::temp_dir().join(format!("bunker-rotate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("debug.log");
+
+        let mut debug = Debug::new(Box::new(DefaultDebugger));
+        debug.replace_sink(Box::new(RotatingFileSink::new(&path).unwrap().max_size(16).keep(1)));
+
+        for n in 0..3 { debug.info("origin", &n.to_string(), &[]); }
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "origin:\n2\n");
+        assert_eq!(std::fs::read_to_string(dir.join("debug.log.1")).unwrap(), "origin:\n1\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Collects every record logged to the `log` facade.
+    #[cfg(feature = "log")]
+    struct CapturingLogger(Mutex<Vec<String>>);
+
+    #[cfg(feature = "log")]
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool { true }
+        fn log(&self, record: &log::Record) {
+            self.0.lock().unwrap().push(format!("{} {} {}", record.level(), record.target(), record.args()));
+        }
+        fn flush(&self) {}
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn log_sink_forwards_records() {
+        static LOGGER: CapturingLogger = CapturingLogger(Mutex::new(Vec::new()));
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let record = Record{ level: Level::Warn, origin: "server::Host::run", message: "Connection denied.", fields: &[("peer", &"10.0.0.1")] };
+        crate::debug::LogSink.write(&record, "formatted entry");
+
+        assert_eq!(*LOGGER.0.lock().unwrap(), vec!["WARN server::Host::run formatted entry".to_string()]);
+    }
+
+    /// Collects the fields of every `tracing` event, as `name=value`.
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct CapturingSubscriber(Arc<Mutex<Vec<String>>>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::field::Visit for CapturingSubscriber {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.lock().unwrap().push(format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for CapturingSubscriber {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool { true }
+        fn new_span(&self, _: &tracing::span::Attributes<'_>) -> tracing::span::Id { tracing::span::Id::from_u64(1) }
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+        fn event(&self, event: &tracing::Event<'_>) { event.record(&mut self.clone()) }
+        fn enter(&self, _: &tracing::span::Id) {}
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_sink_records_fields_separately() {
+        let subscriber = CapturingSubscriber::default();
+        let record = Record{ 
+            level: Level::Info, 
+            origin: "server::Host::say_goodbye", 
+            message: "Connection limit reached.", 
+            fields: &[("ordern", &7), ("peer", &"10.0.0.1:80"), ("limit", &"max_messages")] 
+        };
+
+        tracing::subscriber::with_default(subscriber.clone(), || crate::debug::TracingSink.write(&record, "formatted entry"));
+
+        // Fields without one of their own are joined, and missing fields are left out.
+        assert_eq!(*subscriber.0.lock().unwrap(), vec![
+            "message=Connection limit reached.", 
+            "origin=\"server::Host::say_goodbye\"", 
+            "ordern=7", 
+            "peer=10.0.0.1:80", 
+            "fields=limit=max_messages"
+        ]);
+    }
+
+    #[test]
+    fn json_debugger_escapes() {
+        let sink = MemorySink::new(1);
+        let mut debug = Debug::new(Box::new(JsonDebugger));
+        debug.replace_sink(Box::new(sink.clone()));
+
+        debug.trace("origin", "say \"hi\"\n", &[("ordern", &3), ("data", &Raw(b"a\n\xff"))]);
+
+        let entry = &sink.entries()[0];
+        assert!(entry.starts_with("{\"timestamp\":\""));
+        assert!(entry.contains("\"level\":\"TRACE\",\"origin\":\"origin\",\"ordern\":3,\"thread\":"));
+        assert!(entry.ends_with("\"message\":\"say \\\"hi\\\"\\n\",\"fields\":{\"data\":\"a\\\\n\\\\xff\"}}"));
+        assert_eq!(
+            timestamp(std::time::UNIX_EPOCH + std::time::Duration::from_millis(951_782_400_250)), 
+            "2000-02-29T00:00:00.250Z"
+        );
+    }
+
+    #[test]
+    fn redaction_follows_found_route() {
+        let mut routes = Routes::default();
+        routes.insert(Route::Path("auth".to_string()), Arc::new(Echo)).unwrap();
+        routes.insert(Route::NotFound, Arc::new(Echo)).unwrap();
+        routes.redactions.insert(Route::Path("auth".to_string()), Redaction::LengthOnly);
+
+        let found = routes.find(&Route::Path("auth".to_string()), "auth").unwrap();
+        assert_eq!(found.redaction.apply("hunter2"), "<redacted: 7 bytes>");
+
+        let found = routes.find(&Route::Path("other".to_string()), "other").unwrap();
+        assert!(found.route == Route::NotFound && found.redaction.is_plain());
+
+        // A route registered again after being removed does not inherit the redaction.
+        routes.remove(&Route::Path("auth".to_string()));
+        routes.insert(Route::Path("auth".to_string()), Arc::new(Echo)).unwrap();
+        assert!(routes.find(&Route::Path("auth".to_string()), "auth").unwrap().redaction.is_plain());
+
+        assert_eq!(Redaction::Hidden.apply("hunter2"), "<redacted>");
+        assert_eq!(Redaction::masked(|text| "*".repeat(text.len())).apply("hunter2"), "*******");
+    }
+
+    #[test]
+    fn access_log_records_cycles() {
+        let sink = MemorySink::new(8);
+        let mut stream = connect(41035, Builder::new()
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .max_response_length(99)
+            .register(Box::new(Echo), Route::Path("echo".to_string()))
+            .access_log(Box::new(sink.clone())));
+
+        assert_eq!(request(&mut stream, "echo hi"), "02hi");
+        assert_eq!(request(&mut stream, &format!("echo {}", "x".repeat(100))), "00");
+        assert_eq!(request(&mut stream, "other hi"), "00");
+
+        // Entries are written after the response, so may lag behind the client.
+        while sink.entries().len() < 3 { thread::sleep(Duration::from_millis(10)); }
+
+        let entries = sink.entries();
+        assert!(entries[0].contains("ordern=1 route=echo request_bytes=7 response_bytes=4"));
+        assert!(entries[0].ends_with(&format!("outcome={}", Outcome::Ok)));
+        assert!(entries[1].ends_with("outcome=oversize"));
+        assert!(entries[2].contains("route=- ") && entries[2].ends_with("outcome=internal_error"));
+    }
+
+    #[test]
+    fn metrics_are_collected_and_exposed() {
+        let host = Builder::new()
+            .port(41036)
+            .metrics_port(41136)
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .register(Box::new(Echo), Route::Path("echo".to_string()))
+            .build();
+        let metrics = host.metrics_handle();
+        let mut stream = run(host);
+
+        assert_eq!(request(&mut stream, "echo hi"), "0002hi");
+        request(&mut stream, "other hi");
+
+        while metrics.snapshot().internal_errors < 1 { thread::sleep(Duration::from_millis(10)); }
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.connections_accepted, 1);
+        assert_eq!(snapshot.connections_active, 1);
+        assert_eq!(snapshot.requests.get("echo"), Some(&1));
+        assert_eq!(snapshot.response_size.count, 2);
+        assert_eq!((snapshot.workers, snapshot.busy_workers, snapshot.queue_depth), (1, 1, 0));
+
+        // A client that never sends its request only holds up scraping until it times out.
+        let _stalled = TcpStream::connect(("127.0.0.1", 41136)).unwrap();
+
+        let mut exposition = TcpStream::connect(("127.0.0.1", 41136)).unwrap();
+        let response = request(&mut exposition, "GET /metrics HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+    }
+
+    #[test]
+    fn panicking_workers_leave_gauges_consistent() {
+        let host = Builder::new()
+            .port(41236)
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .register(Box::new(Actions), Route::Path("do".to_string()))
+            .build();
+        let metrics = host.metrics_handle();
+
+        let mut stream = run(host);
+        assert_eq!(request(&mut stream, "do panic"), "");
+
+        // The only worker survives the panic, and the connection is counted as closed.
+        let mut stream = TcpStream::connect(("127.0.0.1", 41236)).unwrap();
+        assert_eq!(request(&mut stream, "do a"), "0001a");
+        drop(stream);
+
+        while metrics.snapshot().connections_active > 0 { thread::sleep(Duration::from_millis(10)); }
+        while metrics.snapshot().busy_workers > 0 { thread::sleep(Duration::from_millis(10)); }
+        assert_eq!(metrics.snapshot().connections_closed, 2);
+    }
+
+    #[test]
+    fn health_route_bypasses_route_map() {
+        let mut stream = connect(41037, Builder::new()
+            .threads(2)
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .health_route("health".to_string())
+            .register(Box::new(Echo), Route::Path("health".to_string())));
+
+        let response = request(&mut stream, "health now");
+        let (length, body) = response.split_at(4);
+
+        assert_eq!(length.parse::<usize>().unwrap(), body.len());
+
+        let fields: Vec<(&str, &str)> = body.split(' ').map(|field| field.split_once('=').unwrap()).collect();
+        let keys: Vec<&str> = fields.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, ["status", "uptime_s", "connections_active", "workers_busy", "workers", "utilization"]);
+
+        let value = |key: &str| fields.iter().find(|(k, _)| *k == key).unwrap().1;
+        assert_eq!(value("status"), "ok");
+        assert!(value("uptime_s").parse::<u64>().is_ok());
+        assert!(value("connections_active").parse::<u64>().unwrap() >= 1);
+        assert!(value("workers_busy").parse::<usize>().unwrap() <= 2);
+        assert_eq!(value("workers"), "2");
+        assert!((0.0..=1.0).contains(&value("utilization").parse::<f64>().unwrap()));
+    }
+
+    #[test]
+    fn rate_limit_drops_after_violations() {
+        let mut stream = connect(41038, Builder::new()
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .register(Box::new(Echo), Route::Path("echo".to_string()))
+            .rate_limit(RateLimit::per_peer(2, Duration::from_secs(60)))
+            .rate_limit(RateLimit::per_route(1, Duration::from_secs(60)).on_route(Route::Path("other".to_string())))
+            .rate_limited_response("slow".to_string())
+            .drop_after_violations(2));
+
+        assert_eq!(request(&mut stream, "echo a"), "0001a");
+        assert_eq!(request(&mut stream, "echo b"), "0001b");
+        assert_eq!(request(&mut stream, "echo c"), "0004slow");
+        assert_eq!(request(&mut stream, "echo d"), "0004slow");
+        assert_eq!(request(&mut stream, "echo e"), "");
+    }
+
+    #[test]
+    fn rate_limiter_prunes_refilled_buckets() {
+        let limiter = RateLimiter::new(vec![RateLimit::per_peer(2, Duration::from_secs(1))]);
+        let start = Instant::now();
+
+        for n in 0..5000_u32 {
+            assert!(limiter.check_at(Some(IpAddr::from(n.to_be_bytes())), None, start));
+        }
+        assert!(limiter.bucket_count() > 4096);
+
+        // Every bucket has refilled a second later, so all but the new peer's are dropped.
+        assert!(limiter.check_at(Some(IpAddr::from([10, 0, 0, 1])), None, start + Duration::from_secs(1)));
+        assert_eq!(limiter.bucket_count(), 1);
+    }
+
+    #[test]
+    fn cidr_filter_closes_denied_connections() {
+        let cidr: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(cidr.contains("10.1.200.3".parse().unwrap()));
+        assert!(!cidr.contains("10.2.0.1".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert_eq!("::1".parse::<Cidr>().unwrap().to_string(), "::1/128");
+
+        let host = Builder::new()
+            .port(41039)
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .register(Box::new(Echo), Route::Path("echo".to_string()))
+            .allow(cidr)
+            .build();
+        let filter = host.filter_handle();
+        let metrics = host.metrics_handle();
+
+        // Loopback is outside the only allowed block, so the first connection is already closed.
+        let mut stream = run(host);
+        assert_eq!(request(&mut stream, "echo a"), "");
+        assert_eq!(metrics.snapshot().connections_denied, 1);
+
+        filter.allow("127.0.0.0/8".parse().unwrap());
+        let mut stream = TcpStream::connect(("127.0.0.1", 41039)).unwrap();
+        assert_eq!(request(&mut stream, "echo b"), "0001b");
+
+        filter.deny("127.0.0.1".parse().unwrap());
+        let mut denied = TcpStream::connect(("127.0.0.1", 41039)).unwrap();
+        assert_eq!(request(&mut denied, "echo c"), "");
+        assert_eq!(request(&mut stream, "echo d"), "0001d");
+
+        assert!(filter.remove("127.0.0.1".parse().unwrap()));
+        assert!(filter.permits("127.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn connection_limits_say_goodbye() {
+        let host = Builder::new()
+            .port(41040)
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .register(Box::new(Echo), Route::Path("echo".to_string()))
+            .max_messages(2)
+            .max_lifetime(Duration::from_millis(300))
+            .goodbye_response("bye".to_string())
+            .build();
+
+        // The goodbye may arrive with the last response or separately.
+        let mut stream = run(host);
+        assert_eq!(request(&mut stream, "echo a"), "0001a");
+        let mut last = request(&mut stream, "echo b");
+        if last == "0001b" { last += &request(&mut stream, ""); }
+        assert_eq!(last, "0001b0003bye");
+        assert_eq!(request(&mut stream, "echo c"), "");
+
+        // An idle connection is closed once its lifetime ends.
+        let mut stream = TcpStream::connect(("127.0.0.1", 41040)).unwrap();
+        let mut buff = [0_u8; 64];
+        let size = stream.read(&mut buff).unwrap();
+        assert_eq!(&buff[..size], b"0003bye");
+    }
+
+    struct Actions;
+
+    impl Controller for Actions {
+        fn respond(&self, msg: String, _: Rc<RefCell<String>>) -> Response {
+            match msg.as_str() {
+                "quiet" => Response::no_reply(),
+                "close" => Response::close(),
+                "bye" => Response::reply_and_close("later".to_string()),
+                "stream" => Response::stream(["a", "", "bb"].into_iter().map(String::from)),
+                "big" => Response::stream(["a".to_string(), "x".repeat(100), "c".to_string()].into_iter()),
+                "panic" => panic!("controller panicked"),
+                _ => msg.into(),
+            }
+        }
+    }
+
+    #[test]
+    fn controller_actions_close_connections() {
+        let mut stream = connect(41041, Builder::new()
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .register(Box::new(Actions), Route::Path("do".to_string()))
+            .endconn_msg("end".to_string()));
+
+        // Nothing is written for a request without a reply, so reading it times out.
+        stream.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        stream.write_all(b"do quiet").unwrap();
+        assert!(stream.read(&mut [0_u8; 16]).is_err());
+        assert_eq!(request(&mut stream, "do a"), "0001a");
+        assert_eq!(request(&mut stream, "do bye"), "0005later");
+        assert_eq!(request(&mut stream, "do b"), "");
+
+        let mut stream = TcpStream::connect(("127.0.0.1", 41041)).unwrap();
+        assert_eq!(request(&mut stream, "do end"), "0021Closing connection...");
+
+        let mut stream = TcpStream::connect(("127.0.0.1", 41041)).unwrap();
+        assert_eq!(request(&mut stream, "do close"), "");
+    }
+
+    #[test]
+    fn close_handshake_waits_for_ack() {
+        let sink = MemorySink::new(16);
+        let mut stream = connect(41042, Builder::new()
+            .debugger_level(Level::Debug)
+            .set_debug_sink(Box::new(sink.clone()))
+            .parse_separator(&[' '])
+            .register(Box::new(Actions), Route::Path("do".to_string()))
+            .endconn_msg("end".to_string())
+            .farewell(Some("ciao".to_string()))
+            .wait_for_close_ack(Duration::from_secs(5)));
+
+        // The server half-closes while still waiting, so the client reads the end of the stream.
+        assert_eq!(request(&mut stream, "do end"), "0004ciao");
+        assert_eq!(stream.read(&mut [0_u8; 16]).unwrap(), 0);
+
+        stream.shutdown(Shutdown::Write).unwrap();
+        while !sink.contains("Client acknowledged close.") { thread::sleep(Duration::from_millis(10)); }
+    }
+
+    #[test]
+    fn request_tasks_wait_for_close_ack() {
+        let sink = MemorySink::new(16);
+        let mut stream = connect(41142, Builder::new()
+            .debugger_level(Level::Debug)
+            .set_debug_sink(Box::new(sink.clone()))
+            .parse_separator(&[' '])
+            .register(Box::new(Actions), Route::Path("do".to_string()))
+            .endconn_msg("end".to_string())
+            .farewell(Some("ciao".to_string()))
+            .wait_for_close_ack(Duration::from_secs(5))
+            .request_tasks(true));
+
+        // A task closing the connection leaves the reader waiting for the acknowledgment too.
+        assert_eq!(request(&mut stream, "do end"), "0004ciao");
+        assert_eq!(stream.read(&mut [0_u8; 16]).unwrap(), 0);
+
+        stream.shutdown(Shutdown::Write).unwrap();
+        while !sink.contains("Client acknowledged close.") { thread::sleep(Duration::from_millis(10)); }
+    }
+
+    #[test]
+    fn quit_command_closes_before_routing() {
+        let mut stream = connect(41043, Builder::new()
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .match_policy(MatchPolicy::exact().case_insensitive())
+            .register(Box::new(Echo), Route::NotFound)
+            .quit_command("QUIT".to_string()));
+
+        assert_eq!(request(&mut stream, "echo quit"), "0004quit");
+        assert_eq!(request(&mut stream, "quit\r\n"), "0021Closing connection...");
+        assert_eq!(request(&mut stream, "echo a"), "");
+    }
+
+    #[test]
+    fn messages_are_pushed_to_clients() {
+        let host = Builder::new()
+            .port(41044)
+            .threads(2)
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .register(Box::new(Echo), Route::Path("echo".to_string()))
+            .build();
+        let push = host.push_handle();
+
+        let mut first = run(host);
+        let mut second = TcpStream::connect(("127.0.0.1", 41044)).unwrap();
+        assert_eq!(request(&mut first, "echo a"), "0001a");
+        assert_eq!(request(&mut second, "echo b"), "0001b");
+        assert_eq!(push.connections(), vec![1, 2]);
+
+        push.send(1, "hi").unwrap();
+        assert_eq!(request(&mut first, ""), "0002hi");
+
+        assert_eq!(push.broadcast("all").unwrap(), 2);
+        assert_eq!(request(&mut first, ""), "0003all");
+        assert_eq!(request(&mut second, ""), "0003all");
+
+        drop(second);
+        while push.connections().len() > 1 { thread::sleep(Duration::from_millis(10)); }
+        assert!(matches!(push.send(2, "gone"), Err(PushError::NotConnected(2))));
+    }
+
+    #[test]
+    fn stalled_clients_are_dropped_from_pushes() {
+        let host = Builder::new()
+            .port(41144)
+            .threads(2)
+            .debugger_level_none()
+            .write_timeout(Duration::from_millis(200))
+            .build();
+        let push = host.push_handle();
+
+        let _stalled = run(host);
+        let mut other = TcpStream::connect(("127.0.0.1", 41144)).unwrap();
+        while push.connections().len() < 2 { thread::sleep(Duration::from_millis(10)); }
+
+        // The client never reads, so its buffers fill until a push times out.
+        let msg = "x".repeat(9000);
+        while push.send(1, &msg).is_ok() {}
+        assert_eq!(push.connections(), vec![2]);
+
+        assert_eq!(push.broadcast("hi").unwrap(), 1);
+        assert_eq!(read_exact(&mut other, 6), "0002hi");
+    }
+
+    struct Publisher(PushHandle);
+
+    impl Controller for Publisher {
+        fn serve(&self, msg: String, _: Rc<RefCell<String>>) -> String {
+            format!("{}", self.0.publish("chat", &msg).unwrap())
+        }
+    }
+
+    #[test]
+    fn topics_receive_published_messages() {
+        let host = Builder::new()
+            .port(41045)
+            .threads(2)
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .subscribe_route("sub".to_string())
+            .unsubscribe_route("unsub".to_string())
+            .build();
+        let push = host.push_handle();
+        host.route_handle().add(Box::new(Publisher(push.clone())), Route::Path("say".to_string())).unwrap();
+
+        let mut listener = run(host);
+        let mut speaker = TcpStream::connect(("127.0.0.1", 41045)).unwrap();
+        assert_eq!(request(&mut listener, "sub chat"), "0019Subscribed to chat.");
+        assert_eq!(request(&mut listener, "sub chat"), "0027Already subscribed to chat.");
+        assert_eq!(push.subscribers("chat"), vec![1]);
+
+        assert_eq!(request(&mut speaker, "say hello"), "00011");
+        assert_eq!(request(&mut listener, ""), "0005hello");
+        assert_eq!(push.publish("chat", "bye").unwrap(), 1);
+        assert_eq!(request(&mut listener, ""), "0003bye");
+
+        assert_eq!(request(&mut listener, "unsub chat"), "0023Unsubscribed from chat.");
+        assert_eq!(request(&mut speaker, "say again"), "00010");
+
+        // Subscriptions end with the connection.
+        assert_eq!(request(&mut speaker, "sub chat"), "0019Subscribed to chat.");
+        drop(speaker);
+        while !push.topics().is_empty() { thread::sleep(Duration::from_millis(10)); }
+    }
+
+    #[test]
+    fn streamed_chunks_end_with_empty_frame() {
+        let mut stream = connect(41046, Builder::new()
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .max_response_length(99)
+            .response_on_error("err".to_string())
+            .register(Box::new(Actions), Route::Path("do".to_string())));
+
+        // Frames may arrive together or apart, so reads continue until the end marker.
+        let mut read_stream = |req: &str| {
+            let mut res = request(&mut stream, req);
+            while !res.ends_with("00") { res += &request(&mut stream, ""); }
+            res
+        };
+
+        assert_eq!(read_stream("do stream"), "01a02bb00");
+        assert_eq!(read_stream("do big"), "01a03err00");
+        assert_eq!(request(&mut stream, "do a"), "01a");
+    }
+
+    /// Sleeps for the milliseconds given, then replies with them.
+    struct Sleep;
+
+    impl Controller for Sleep {
+        fn serve(&self, msg: String, _: Rc<RefCell<String>>) -> String {
+            thread::sleep(Duration::from_millis(msg.parse().unwrap_or(0)));
+            msg
+        }
+    }
+
+    /// Holds every request until the given number of requests have arrived, then replies with the message.
+    /// The first request to arrive also waits for every other to be answered. Replies "serial" if the others never arrive.
+    struct Gate {
+        expected: u32,
+        state: Arc<(Mutex<(u32, u32)>, Condvar)>
+    }
+
+    impl Gate {
+        fn new(expected: u32) -> Gate { Gate{ expected, state: Default::default() } }
+    }
+
+    impl Controller for Gate {
+        fn serve(&self, msg: String, _: Rc<RefCell<String>>) -> String {
+            let (lock, cvar) = &*self.state;
+            let timeout = Duration::from_secs(5);
+
+            let mut state = lock.lock().unwrap();
+            state.0 += 1;
+            let first = state.0 == 1;
+            cvar.notify_all();
+
+            let (mut state, waited) = cvar.wait_timeout_while(state, timeout, |(arrived, _)| *arrived < self.expected).unwrap();
+            if waited.timed_out() { return "serial".to_string(); }
+
+            if first {
+                state = cvar.wait_timeout_while(state, timeout, |(_, answered)| *answered < self.expected - 1).unwrap().0;
+            }
+            state.1 += 1;
+            cvar.notify_all();
+
+            msg
+        }
+    }
+
+    /// Reads until the expected number of bytes arrive.
+    fn read_exact(stream: &mut TcpStream, len: usize) -> String {
+        let mut res = vec![0_u8; len];
+        stream.read_exact(&mut res).unwrap();
+        String::from_utf8(res).unwrap()
+    }
+
+    #[test]
+    fn pipelined_requests_answer_in_order() {
+        let mut stream = connect(41047, Builder::new()
+            .threads(3)
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .register(Box::new(Echo), Route::Path("echo".to_string()))
+            .register(Box::new(Gate::new(3)), Route::Path("gate".to_string()))
+            .request_delimiter("\n".to_string())
+            .concurrent_pipelining(true));
+
+        // Incomplete requests wait for the rest of their bytes.
+        stream.write_all(b"echo a\necho b\n\necho c\nech").unwrap();
+        assert_eq!(read_exact(&mut stream, 15), "0001a0001b0001c");
+        stream.write_all(b"o d\n").unwrap();
+        assert_eq!(read_exact(&mut stream, 5), "0001d");
+
+        // Every request waits for the others to arrive, so they are only answered if served concurrently,
+        // and the first one answered last is still written first.
+        stream.write_all(b"gate a\ngate b\ngate c\n").unwrap();
+        assert_eq!(read_exact(&mut stream, 15), "0001a0001b0001c");
+    }
+
+    #[test]
+    fn pipelined_panics_close_in_order() {
+        let mut stream = connect(41147, Builder::new()
+            .threads(4)
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .register(Box::new(Actions), Route::Path("do".to_string()))
+            .request_delimiter("\n".to_string())
+            .concurrent_pipelining(true));
+
+        // The panicking request is never answered by a later response, so the connection closes right after the one before it.
+        stream.write_all(b"do a\ndo panic\ndo b\ndo c\n").unwrap();
+        assert_eq!(read_exact(&mut stream, 5), "0001a");
+        assert_eq!(stream.read(&mut [0_u8; 16]).unwrap_or(0), 0);
+    }
+
+    #[test]
+    fn request_ids_answer_as_completed() {
+        let mut stream = connect(41048, Builder::new()
+            .threads(3)
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .register(Box::new(Sleep), Route::Path("sleep".to_string()))
+            .register(Box::new(Actions), Route::Path("do".to_string()))
+            .request_delimiter("\n".to_string())
+            .request_ids(':'));
+
+        stream.write_all(b"a:sleep 300\nb:sleep 10\n").unwrap();
+        assert_eq!(read_exact(&mut stream, 17), "b:000210a:0003300");
+
+        // Every frame of a stream carries the ID.
+        stream.write_all(b"7:do stream\n").unwrap();
+        assert_eq!(read_exact(&mut stream, 21), "7:0001a7:0002bb7:0000");
+    }
+
+    #[test]
+    fn request_tasks_share_workers_between_connections() {
+        let mut first = connect(41049, Builder::new()
+            .threads(2)
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .register(Box::new(Sleep), Route::Path("sleep".to_string()))
+            .register(Box::new(Actions), Route::Path("do".to_string()))
+            .request_delimiter("\n".to_string())
+            .request_tasks(true));
+
+        // Idle connections take no worker, so more connections than workers are served.
+        let mut others: Vec<TcpStream> = (0..3).map(|_| TcpStream::connect(("127.0.0.1", 41049)).unwrap()).collect();
+        for (n, other) in others.iter_mut().enumerate() {
+            assert_eq!(request(other, &format!("do {}\n", n)), format!("0001{}", n));
+        }
+
+        // Requests of one connection are served concurrently, yet answered in request order.
+        first.write_all(b"sleep 200\nsleep 10\ndo bye\n").unwrap();
+        assert_eq!(read_exact(&mut first, 22), "00032000002100005later");
+        assert_eq!(first.read(&mut [0_u8; 16]).unwrap_or(0), 0);
+    }
+
+    #[test]
+    fn panicking_tasks_release_workers() {
+        let mut stream = connect(41149, Builder::new()
+            .debugger_level_none()
+            .parse_position(3)
+            .register(Box::new(Actions), Route::Path("do ".to_string()))
+            .register(Box::new(Echo), Route::NotFound)
+            .request_delimiter("\n".to_string())
+            .request_tasks(true));
+
+        // Requests too short for the position, or split inside a character, match no path.
+        assert_eq!(request(&mut stream, "d\n"), "0001d");
+        assert_eq!(request(&mut stream, "ddé\n"), "0004ddé");
+
+        // The panicking request closes its connection, while the only worker stays available.
+        assert_eq!(request(&mut stream, "do a\ndo panic\ndo b\n"), "0001a");
+        assert_eq!(stream.read(&mut [0_u8; 16]).unwrap_or(0), 0);
+
+        let mut stream = TcpStream::connect(("127.0.0.1", 41149)).unwrap();
+        assert_eq!(request(&mut stream, "do c\n"), "0001c");
+    }
+
+    /// Records the most requests it served at once, holding each for a moment.
+    #[derive(Clone, Default)]
+    struct Peak {
+        current: Arc<AtomicUsize>,
+        peak: Arc<AtomicUsize>
+    }
+
+    impl Controller for Peak {
+        fn serve(&self, msg: String, _: Rc<RefCell<String>>) -> String {
+            let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(current, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            self.current.fetch_sub(1, Ordering::SeqCst);
+            msg
+        }
+    }
+
+    /// Pipelines more requests than a connection may have in flight, returning the most served at once.
+    fn pipeline_past_limit(port: u16, builder: Builder) -> usize {
+        let peak = Peak::default();
+        let mut stream = connect(port, builder
+            .threads(4)
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .register(Box::new(peak.clone()), Route::Path("peak".to_string()))
+            .request_delimiter("\n".to_string())
+            .max_in_flight(2));
+
+        stream.write_all(b"peak a\npeak b\npeak c\npeak d\npeak e\n").unwrap();
+        assert_eq!(read_exact(&mut stream, 25), "0001a0001b0001c0001d0001e");
+        peak.peak.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn request_tasks_bound_requests_in_flight() {
+        assert!(pipeline_past_limit(41349, Builder::new().request_tasks(true)) <= 2);
+    }
+
+    #[cfg(feature = "mio")]
+    #[test]
+    fn event_loop_bounds_requests_in_flight() {
+        assert!(pipeline_past_limit(41150, Builder::new().event_loop(true)) <= 2);
+    }
+
+    /// Closes connections after a short lifetime, serving requests as separate tasks.
+    fn short_lived() -> Builder {
+        Builder::new()
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .register(Box::new(Sleep), Route::Path("sleep".to_string()))
+            .request_delimiter("\n".to_string())
+            .max_lifetime(Duration::from_millis(200))
+            .goodbye_response("bye".to_string())
+    }
+
+    #[test]
+    fn request_tasks_answer_before_lifetime_goodbye() {
+        let mut stream = connect(41249, short_lived().request_tasks(true));
+
+        // The request is still being served when the lifetime ends.
+        stream.write_all(b"sleep 400\n").unwrap();
+        assert_eq!(read_exact(&mut stream, 14), "00034000003bye");
+        assert_eq!(stream.read(&mut [0_u8; 16]).unwrap_or(0), 0);
+    }
+
+    #[cfg(feature = "mio")]
+    #[test]
+    fn event_loop_answers_before_lifetime_goodbye() {
+        let mut stream = connect(41250, short_lived().event_loop(true));
+
+        stream.write_all(b"sleep 400\n").unwrap();
+        assert_eq!(read_exact(&mut stream, 14), "00034000003bye");
+        assert_eq!(stream.read(&mut [0_u8; 16]).unwrap_or(0), 0);
+    }
+
+    #[cfg(feature = "mio")]
+    #[test]
+    fn event_loop_serves_idle_connections_without_threads() {
+        let host = Builder::new()
+            .port(41050)
+            .debugger_level_none()
+            .parse_separator(&[' '])
+            .register(Box::new(Sleep), Route::Path("sleep".to_string()))
+            .register(Box::new(Actions), Route::Path("do".to_string()))
+            .request_delimiter("\n".to_string())
+            .farewell(Some("ciao".to_string()))
+            .endconn_msg("end".to_string())
+            .wait_for_close_ack(Duration::from_secs(5))
+            .event_loop(true)
+            .build();
+        let push = host.push_handle();
+        let mut first = run(host);
+
+        // A single worker serves far more connections than it could hold.
+        let mut others: Vec<TcpStream> = (0..64).map(|_| TcpStream::connect(("127.0.0.1", 41050)).unwrap()).collect();
+        for (n, other) in others.iter_mut().enumerate() {
+            assert_eq!(request(other, &format!("do {}\n", n % 10)), format!("0001{}", n % 10));
+        }
+        assert_eq!(push.broadcast("hi").unwrap(), 65);
+        assert_eq!(read_exact(&mut others[0], 6), "0002hi");
+
+        // Requests are still answered in request order, and closed with the handshake.
+        assert_eq!(read_exact(&mut first, 6), "0002hi");
+        first.write_all(b"sleep 50\ndo a\ndo end\n").unwrap();
+        assert_eq!(read_exact(&mut first, 19), "0002500001a0004ciao");
+        assert_eq!(first.read(&mut [0_u8; 16]).unwrap(), 0);
+        first.shutdown(Shutdown::Write).unwrap();
+
+        while push.connections().len() > 64 { thread::sleep(Duration::from_millis(10)); }
+    }
+
     #[test]
     fn worker_count() {
         let threadpool = Threadpool::new(3);
@@ -0,0 +1,269 @@
+//! Counters and histograms describing a running `server::Host`, 
+//! read through a `MetricsHandle` as a `Snapshot`.
+
+use std::{collections::BTreeMap, fmt::Write as _, io::{ErrorKind, Read, Write}, net::TcpListener, sync::{Arc, Mutex, OnceLock, atomic::{AtomicU64, Ordering}}, time::{Duration, Instant}};
+
+use crate::{internal::PoolStats, registerable::Outcome};
+
+/// Upper bounds of the latency buckets, in seconds.
+const LATENCY_BOUNDS: [f64; 10] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0];
+/// Upper bounds of the response size buckets, in bytes.
+const SIZE_BOUNDS: [f64; 7] = [64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0];
+
+/// Longest the exposition endpoint waits on a single client to read its request or write the response.
+const EXPOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Distribution of observed values, with counts per bucket.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    /// Upper bound of each bucket, in increasing order.
+    pub bounds: Vec<f64>,
+    /// Number of values at or below each bound, so counts are cumulative.
+    pub counts: Vec<u64>,
+    pub sum: f64,
+    pub count: u64
+}
+
+impl Histogram {
+    fn new(bounds: &[f64]) -> Histogram {
+        Histogram{ bounds: bounds.to_vec(), counts: vec![0; bounds.len()], sum: 0.0, count: 0 }
+    }
+
+    fn observe(&mut self, value: f64) {
+        self.bounds.iter()
+            .zip(self.counts.iter_mut())
+            .filter(|(bound, _)| value <= **bound)
+            .for_each(|(_, count)| *count += 1);
+
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// Writes the histogram in Prometheus text format, with an optional route label.
+    fn expose(&self, out: &mut String, name: &str, route: Option<&str>) {
+        let label = |extra: String| match route {
+            Some(route) if extra.is_empty() => format!("{{route=\"{}\"}}", escape(route)),
+            Some(route) => format!("{{route=\"{}\",{}}}", escape(route), extra),
+            None if extra.is_empty() => String::new(),
+            None => format!("{{{}}}", extra),
+        };
+
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter()) {
+            let _ = writeln!(out, "{}_bucket{} {}", name, label(format!("le=\"{}\"", bound)), count);
+        }
+        let _ = writeln!(out, "{}_bucket{} {}", name, label(String::from("le=\"+Inf\"")), self.count);
+        let _ = writeln!(out, "{}_sum{} {}", name, label(String::new()), self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, label(String::new()), self.count);
+    }
+}
+
+/// Escapes a label value for the Prometheus text format.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[derive(Clone)]
+struct RouteStats {
+    requests: u64,
+    latency: Histogram
+}
+
+/// Registry updated by the server as connections and requests are served.
+pub(crate) struct Metrics {
+    accepted: AtomicU64,
+    closed: AtomicU64,
+    denied: AtomicU64,
+    controller_errors: AtomicU64,
+    internal_errors: AtomicU64,
+    oversize: AtomicU64,
+    rate_limited: AtomicU64,
+    sizes: Mutex<Histogram>,
+    routes: Mutex<BTreeMap<String, RouteStats>>,
+    /// Set once the threadpool is created.
+    pool: OnceLock<Arc<PoolStats>>,
+    /// Set once the server starts running.
+    started: OnceLock<Instant>
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            accepted: AtomicU64::new(0),
+            closed: AtomicU64::new(0),
+            denied: AtomicU64::new(0),
+            controller_errors: AtomicU64::new(0),
+            internal_errors: AtomicU64::new(0),
+            oversize: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0),
+            sizes: Mutex::new(Histogram::new(&SIZE_BOUNDS)),
+            routes: Mutex::new(BTreeMap::new()),
+            pool: OnceLock::new(),
+            started: OnceLock::new()
+        }
+    }
+
+    pub fn attach_pool(&self, pool: Arc<PoolStats>) { let _ = self.pool.set(pool); }
+    pub fn start(&self) { let _ = self.started.set(Instant::now()); }
+
+    /// Time since the server started running, or zero if it has not.
+    pub fn uptime(&self) -> Duration { self.started.get().map_or(Duration::ZERO, Instant::elapsed) }
+
+    pub fn active(&self) -> u64 {
+        self.accepted.load(Ordering::Relaxed).saturating_sub(self.closed.load(Ordering::Relaxed))
+    }
+
+    /// Returns the busy and total workers of the threadpool.
+    pub fn workers(&self) -> (usize, usize) { self.pool.get().map_or((0, 0), |pool| (pool.busy(), pool.size())) }
+
+    pub fn connection_accepted(&self) { self.accepted.fetch_add(1, Ordering::Relaxed); }
+    pub fn connection_closed(&self) { self.closed.fetch_add(1, Ordering::Relaxed); }
+    pub fn connection_denied(&self) { self.denied.fetch_add(1, Ordering::Relaxed); }
+
+    /// Records a request/response cycle. The route is `None` if no controller matched.
+    pub fn request(&self, route: Option<String>, outcome: Outcome, response_bytes: usize, latency: Duration) {
+        match outcome {
+            Outcome::Ok => (),
+            Outcome::ControllerError => { self.controller_errors.fetch_add(1, Ordering::Relaxed); },
+            Outcome::InternalError => { self.internal_errors.fetch_add(1, Ordering::Relaxed); },
+            Outcome::Oversize => { self.oversize.fetch_add(1, Ordering::Relaxed); },
+            Outcome::RateLimited => { self.rate_limited.fetch_add(1, Ordering::Relaxed); },
+        }
+
+        self.sizes.lock().unwrap().observe(response_bytes as f64);
+
+        if let Some(route) = route {
+            let mut routes = self.routes.lock().unwrap();
+            let stats = routes.entry(route).or_insert_with(|| RouteStats{ requests: 0, latency: Histogram::new(&LATENCY_BOUNDS) });
+
+            stats.requests += 1;
+            stats.latency.observe(latency.as_secs_f64());
+        }
+    }
+}
+
+/// Point-in-time copy of every metric of a server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub connections_accepted: u64,
+    pub connections_active: u64,
+    pub connections_closed: u64,
+    /// Connections closed on accept, as the address of the client was denied.
+    pub connections_denied: u64,
+    pub uptime: Duration,
+    /// Requests served, keyed by the route they were matched to.
+    pub requests: BTreeMap<String, u64>,
+    pub controller_errors: u64,
+    pub internal_errors: u64,
+    pub oversize_responses: u64,
+    pub rate_limited: u64,
+    /// Bytes written per response, including the length prefix.
+    pub response_size: Histogram,
+    /// Seconds from receiving a request to writing its response, keyed by route.
+    pub latency: BTreeMap<String, Histogram>,
+    /// Tasks waiting for a worker of the threadpool.
+    pub queue_depth: usize,
+    /// Workers of the threadpool currently running a task.
+    pub busy_workers: usize,
+    pub workers: usize
+}
+
+impl Snapshot {
+    /// Formats the snapshot in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        let mut metric = |name: &str, help: &str, kind: &str, value: String| {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value);
+        };
+
+        metric("bunker_uptime_seconds", "Time since the server started running.", "gauge", self.uptime.as_secs_f64().to_string());
+        metric("bunker_connections_accepted_total", "Connections accepted.", "counter", self.connections_accepted.to_string());
+        metric("bunker_connections_active", "Connections currently open.", "gauge", self.connections_active.to_string());
+        metric("bunker_connections_closed_total", "Connections closed.", "counter", self.connections_closed.to_string());
+        metric("bunker_connections_denied_total", "Connections closed on accept by the address filter.", "counter", self.connections_denied.to_string());
+        metric("bunker_controller_errors_total", "Requests where the controller reported an error.", "counter", self.controller_errors.to_string());
+        metric("bunker_internal_errors_total", "Requests bunker failed to serve.", "counter", self.internal_errors.to_string());
+        metric("bunker_oversize_responses_total", "Responses exceeding the max response length.", "counter", self.oversize_responses.to_string());
+        metric("bunker_rate_limited_total", "Requests exceeding a rate limit.", "counter", self.rate_limited.to_string());
+        metric("bunker_threadpool_queue_depth", "Tasks waiting for a worker.", "gauge", self.queue_depth.to_string());
+        metric("bunker_threadpool_busy_workers", "Workers running a task.", "gauge", self.busy_workers.to_string());
+        metric("bunker_threadpool_workers", "Workers in the threadpool.", "gauge", self.workers.to_string());
+
+        let _ = writeln!(out, "# HELP bunker_requests_total Requests served per route.\n# TYPE bunker_requests_total counter");
+        for (route, count) in &self.requests {
+            let _ = writeln!(out, "bunker_requests_total{{route=\"{}\"}} {}", escape(route), count);
+        }
+
+        let _ = writeln!(out, "# HELP bunker_response_size_bytes Bytes written per response.\n# TYPE bunker_response_size_bytes histogram");
+        self.response_size.expose(&mut out, "bunker_response_size_bytes", None);
+
+        let _ = writeln!(out, "# HELP bunker_request_duration_seconds Latency per route.\n# TYPE bunker_request_duration_seconds histogram");
+        for (route, latency) in &self.latency {
+            latency.expose(&mut out, "bunker_request_duration_seconds", Some(route));
+        }
+
+        out
+    }
+}
+
+/// Handle for reading the metrics of a `Host`, including while it is running.
+/// Obtained through `Host::metrics_handle`, and can be cloned and sent across threads.
+#[derive(Clone)]
+pub struct MetricsHandle(Arc<Metrics>);
+
+impl MetricsHandle {
+    pub(crate) fn new(metrics: Arc<Metrics>) -> MetricsHandle { MetricsHandle(metrics) }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let metrics = &self.0;
+        let routes = metrics.routes.lock().unwrap().clone();
+        let (busy_workers, workers) = metrics.workers();
+
+        Snapshot {
+            connections_accepted: metrics.accepted.load(Ordering::Relaxed),
+            connections_active: metrics.active(),
+            connections_closed: metrics.closed.load(Ordering::Relaxed),
+            connections_denied: metrics.denied.load(Ordering::Relaxed),
+            uptime: metrics.uptime(),
+            requests: routes.iter().map(|(route, stats)| (route.clone(), stats.requests)).collect(),
+            controller_errors: metrics.controller_errors.load(Ordering::Relaxed),
+            internal_errors: metrics.internal_errors.load(Ordering::Relaxed),
+            oversize_responses: metrics.oversize.load(Ordering::Relaxed),
+            rate_limited: metrics.rate_limited.load(Ordering::Relaxed),
+            response_size: metrics.sizes.lock().unwrap().clone(),
+            latency: routes.into_iter().map(|(route, stats)| (route, stats.latency)).collect(),
+            queue_depth: metrics.pool.get().map_or(0, |pool| pool.queued()),
+            busy_workers,
+            workers
+        }
+    }
+
+    /// Serves the snapshot in Prometheus text format over HTTP to every connection on the listener,
+    /// regardless of the request. Blocks for as long as the listener accepts connections.
+    pub(crate) fn expose(&self, listener: TcpListener) -> std::io::Result<()> {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+
+            // A client that stalls only holds up scraping until the timeout.
+            let _ = stream.set_read_timeout(Some(EXPOSE_TIMEOUT));
+            let _ = stream.set_write_timeout(Some(EXPOSE_TIMEOUT));
+
+            // The request is only read so the client is not reset before receiving the response.
+            let mut buff = [0_u8; 1024];
+            let _ = stream.read(&mut buff);
+
+            let body = self.snapshot().to_prometheus();
+            let _ = write!(
+                stream, 
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", 
+                body.len(), body
+            );
+        }
+
+        Ok(())
+    }
+}
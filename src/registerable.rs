@@ -1,24 +1,172 @@
-use std::{cell::RefCell, rc::Rc, fmt};
+use std::{borrow::Cow, cell::RefCell, rc::Rc, fmt, collections::HashMap, net::IpAddr, str::FromStr, sync::Arc, time::{Duration, SystemTime}};
 
-use crate::exception::BunkerError;
+use crate::exception::{BunkerError, ParseCidrError};
 
-#[derive(PartialEq, PartialOrd, Eq, Ord)]
+#[derive(PartialEq, PartialOrd, Eq, Ord, Clone, Debug)]
 pub enum Route {
     NotFound,
-    Path(String)
+    Path(String),
+    /// Matches the whole request against the given pattern, trimmed of surrounding whitespace 
+    /// and regardless of the parse options. Tried after every `Route::Path`
+    /// and before `Route::NotFound`, in the order of registration.
+    /// The controller still receives the message split off by the parse options.
+    /// 
+    /// *The pattern is compiled when the server is built, and an invalid pattern will panic, see `Builder::try_build`.*
+    #[cfg(feature = "regex")]
+    Regex(String)
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Route::NotFound => write!(f, "<not found>"),
+            Route::Path(path) => write!(f, "{}", path),
+            #[cfg(feature = "regex")]
+            Route::Regex(pattern) => write!(f, "/{}/", pattern),
+        }
+    }
+}
+
+/// Describes a registered route, as listed by `Host::routes` and `RouteHandle::routes`.
+#[derive(Clone, Debug)]
+pub struct RouteInfo {
+    pub route: Route,
+    /// Type name of the controller registered to the route.
+    pub controller: &'static str,
+    pub description: Option<String>
+}
+
+impl fmt::Display for RouteInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.description {
+            Some(description) => write!(f, "{}\t{}\t{}", self.route, self.controller, description),
+            None => write!(f, "{}\t{}", self.route, self.controller),
+        }
+    }
+}
+
+/// How requests and responses of a route appear in debug records.
+/// Configured per route through `RouteMapBuilder::redact`, defaulting to `Redaction::Plain`.
+#[derive(Clone, Default)]
+pub enum Redaction {
+    /// Logged as received or sent.
+    #[default]
+    Plain,
+    /// Replaced entirely.
+    Hidden,
+    /// Replaced with its length in bytes.
+    LengthOnly,
+    /// Replaced with the output of the given masking function.
+    Masked(Arc<dyn Fn(&str) -> String + Send + Sync>)
+}
+
+impl Redaction {
+    /// Creates a redaction from a masking function.
+    pub fn masked<F>(f: F) -> Redaction 
+        where 
+            F: Fn(&str) -> String + Send + Sync + 'static
+    {
+        Redaction::Masked(Arc::new(f))
+    }
+
+    /// Checks if text is logged unchanged.
+    pub fn is_plain(&self) -> bool { matches!(self, Redaction::Plain) }
+
+    /// Transforms the text into the form written to the debugger.
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self {
+            Redaction::Plain => Cow::Borrowed(text),
+            Redaction::Hidden => Cow::Borrowed("<redacted>"),
+            Redaction::LengthOnly => Cow::Owned(format!("<redacted: {} bytes>", text.len())),
+            Redaction::Masked(f) => Cow::Owned(f(text)),
+        }
+    }
+}
+
+/// Named capture groups of a `Route::Regex` match, keyed by group name.
+pub type Captures = HashMap<String, String>;
+
+/// What bunker does with the connection once a controller has handled a request.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Action {
+    /// Writes the body and waits for the next request.
+    Reply,
+    /// Writes the body, then closes the connection.
+    ReplyAndClose,
+    /// Closes the connection without writing anything.
+    Close,
+    /// Writes nothing and waits for the next request.
+    NoReply,
+    /// Writes each chunk as its own frame, followed by an empty frame marking the end of the stream, 
+    /// then waits for the next request.
+    Stream
+}
+
+/// Chunks of a streamed response, produced lazily as they are written.
+pub type Chunks = Box<dyn Iterator<Item = String>>;
+
+/// Response returned from `Controller::respond`, pairing the body with an `Action`.
+/// 
+/// A `String` converts into `Response::reply`.
+pub struct Response {
+    body: String,
+    chunks: Option<Chunks>,
+    action: Action
+}
+
+impl Response {
+    pub fn reply(body: String) -> Response { Response{ body, chunks: None, action: Action::Reply } }
+    pub fn reply_and_close(body: String) -> Response { Response{ body, chunks: None, action: Action::ReplyAndClose } }
+    pub fn close() -> Response { Response{ body: String::new(), chunks: None, action: Action::Close } }
+    pub fn no_reply() -> Response { Response{ body: String::new(), chunks: None, action: Action::NoReply } }
+
+    /// Streams the chunks, each of which may be up to the max response length.
+    /// Empty chunks are skipped, so they cannot be mistaken for the end of the stream.
+    /// 
+    /// Errors should be saved in `out_debug` before returning, as chunks are only produced once the response is written.
+    pub fn stream<I: Iterator<Item = String> + 'static>(chunks: I) -> Response {
+        Response{ body: String::new(), chunks: Some(Box::new(chunks)), action: Action::Stream }
+    }
+
+    pub fn get_body(&self) -> &str { &self.body }
+    pub fn get_action(&self) -> Action { self.action }
+
+    /// Checks if anything is written to the client.
+    pub fn is_written(&self) -> bool { matches!(self.action, Action::Reply | Action::ReplyAndClose | Action::Stream) }
+
+    /// Checks if the connection is closed afterwards.
+    pub fn is_closing(&self) -> bool { matches!(self.action, Action::ReplyAndClose | Action::Close) }
+
+    pub(crate) fn into_parts(self) -> (String, Option<Chunks>) { (self.body, self.chunks) }
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Response").field("body", &self.body).field("action", &self.action).finish()
+    }
+}
+
+impl From<String> for Response {
+    fn from(body: String) -> Response { Response::reply(body) }
+}
+
+impl From<&str> for Response {
+    fn from(body: &str) -> Response { Response::reply(body.to_string()) }
 }
 
 /// Basic interface for accepting any request and returning a response.
 /// `Controller::serve` will be called if a path matches this controller, so
 /// provide an implementation for response-writing logic.
 /// 
+/// Implement `Controller::respond` instead to close the connection or to not reply.
+/// 
 /// Expects a response even if an error occurred.
 /// Any error should be saved in `out_debug`, which would then be passed to the debugger.
 /// 
 /// **WARNING:** *Default implementation will panic.*
 #[allow(unused_variables)]
 pub trait Controller : Send + Sync {
-    #[deprecated(since="0.2", note="use `Controller::serve` instead.")]
+    #[deprecated(since="0.2.0", note="use `Controller::serve` instead.")]
     fn accept(&self, msg: String) -> Result<String, BunkerError> {
         panic!("Provide implementation of Controller::serve.")
     }
@@ -27,7 +175,7 @@ pub trait Controller : Send + Sync {
     /// 
     /// - `msg` The request received from the client, with the path prefix removed.
     /// - `out_debug` Any errors should be converted to a string and stored in here.
-    ///     If filled, the inner string will be passed to `Debug::write_err`.
+    ///   If filled, the inner string is written as an error record, and the response on error is sent instead.
     fn serve(&self, msg: String, out_debug: Rc<RefCell<String>>) -> String {
         match self.accept(msg) {
             Ok(res) => res,
@@ -37,6 +185,37 @@ pub trait Controller : Send + Sync {
             },
         }
     }
+
+    /// Is called instead of `Controller::serve` if the request was matched by a `Route::Regex`.
+    /// 
+    /// - `captures` The named capture groups from the matching pattern.
+    /// 
+    /// Default implementation ignores the captures and calls `Controller::serve`.
+    fn serve_captures(&self, msg: String, captures: Captures, out_debug: Rc<RefCell<String>>) -> String {
+        self.serve(msg, out_debug)
+    }
+
+    /// Is called by bunker for every request matched to this controller, 
+    /// deciding whether to reply, close the connection, or both.
+    /// 
+    /// Default implementation replies with `Controller::serve`.
+    fn respond(&self, msg: String, out_debug: Rc<RefCell<String>>) -> Response {
+        Response::reply(self.serve(msg, out_debug))
+    }
+
+    /// Is called instead of `Controller::respond` if the request was matched by a `Route::Regex`.
+    /// 
+    /// Default implementation replies with `Controller::serve_captures`, 
+    /// so implement this as well if `Controller::respond` is implemented and the controller is registered to a pattern.
+    fn respond_captures(&self, msg: String, captures: Captures, out_debug: Rc<RefCell<String>>) -> Response {
+        Response::reply(self.serve_captures(msg, captures, out_debug))
+    }
+
+    /// Name of the controller when listing routes. Defaults to the type name of the implementation.
+    fn type_name(&self) -> &'static str { std::any::type_name::<Self>() }
+
+    /// Optional description of the controller when listing routes.
+    fn description(&self) -> Option<String> { None }
 }
 
 /// For custom implementations of Bunker's formatter for debugging..
@@ -45,11 +224,133 @@ pub trait Controller : Send + Sync {
 /// either stdout or stderr respectively. `debug_err` has a default implementation which only calls `debug`.
 /// Unless a custom implementation of `debug_err` is provided, 
 /// the only difference is it will write to stderr instead of stdout.
+/// 
+/// Implement `DebugFmt::format` instead to have access to the level and fields of each record.
 pub trait DebugFmt: Send + Sync {
     fn debug(&self, origin: &str, message: &str) -> String;
     fn debug_err(&self, origin: &str, message: &str) -> String { self.debug(origin, message) }
+
+    /// Formats a record into a single entry for a `DebugSink`.
+    /// 
+    /// Default implementation appends the fields to the message as `key=value` pairs, 
+    /// then calls `debug_err` for warnings and errors, or `debug` otherwise.
+    fn format(&self, record: &Record) -> String {
+        let mut message = record.message.to_string();
+        for (key, value) in record.fields {
+            message += &format!(" {}={}", key, value);
+        }
+
+        if record.level >= Level::Warn { 
+            self.debug_err(record.origin, &message) 
+        } else { 
+            self.debug(record.origin, &message) 
+        }
+    }
 }
 
+/// Severity of a debug record, from most to least verbose.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Trace => write!(f, "TRACE"),
+            Level::Debug => write!(f, "DEBUG"),
+            Level::Info => write!(f, "INFO"),
+            Level::Warn => write!(f, "WARN"),
+            Level::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+/// A named value attached to a debug record, such as the order number or the peer address.
+pub type Field<'a> = (&'static str, &'a dyn fmt::Display);
+
+/// A single message from bunker's debugger.
+pub struct Record<'a> {
+    pub level: Level,
+    pub origin: &'a str,
+    pub message: &'a str,
+    pub fields: &'a [Field<'a>]
+}
+
+impl Record<'_> {
+    /// Finds the value of a field by its key.
+    pub fn field(&self, key: &str) -> Option<&dyn fmt::Display> {
+        self.fields.iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| *value)
+    }
+}
+
+/// Destination for debug records, once formatted by a `DebugFmt`.
+/// 
+/// The record is passed along with the formatted entry, for sinks that 
+/// forward the structured fields elsewhere.
+pub trait DebugSink: Send + Sync {
+    fn write(&self, record: &Record, entry: &str);
+}
+
+/// Result of a single request/response cycle, as written to the access log.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Outcome {
+    Ok,
+    /// The controller reported an error through `out_debug`.
+    ControllerError,
+    /// Bunker failed to serve the request, such as when no controller matched.
+    InternalError,
+    /// The response exceeded the max response length, and was replaced with the response on error.
+    Oversize,
+    /// The request exceeded a rate limit, and was answered with the rate limited response.
+    RateLimited
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Ok => write!(f, "ok"),
+            Outcome::ControllerError => write!(f, "controller_error"),
+            Outcome::InternalError => write!(f, "internal_error"),
+            Outcome::Oversize => write!(f, "oversize"),
+            Outcome::RateLimited => write!(f, "rate_limited"),
+        }
+    }
+}
+
+/// A single entry of the access log, written once per request/response cycle.
+pub struct Access<'a> {
+    /// When the request was received.
+    pub time: SystemTime,
+    pub peer: &'a str,
+    pub ordern: u64,
+    /// The route the request was matched to, or `None` if no controller matched.
+    pub route: Option<&'a Route>,
+    pub request_bytes: usize,
+    /// Bytes written in reply, including the length prefix.
+    pub response_bytes: usize,
+    /// Time from receiving the request to writing the response.
+    pub latency: Duration,
+    pub outcome: Outcome
+}
+
+/// Formats an access log entry into a single line.
+pub trait AccessFmt: Send + Sync {
+    fn format(&self, access: &Access) -> String;
+}
+
+/// Destination for access log lines, once formatted by an `AccessFmt`.
+pub trait AccessSink: Send + Sync {
+    fn write(&self, line: &str);
+}
+
+/// Coarse debugger levels from 0.2, mapped onto `Level`. Both `Standard` and `Error` write every record.
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum DebugSetting {
     None,
@@ -67,6 +368,187 @@ impl fmt::Display for DebugSetting {
     }
 }
 
+/// Unicode normalization form applied to paths by a `MatchPolicy`.
+#[cfg(feature = "unicode")]
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Normalization {
+    Nfc,
+    Nfkc
+}
+
+/// Describes how paths are compared when matching a request to a `Route::Path`.
+/// 
+/// The same transformation is applied to every path given at registration time 
+/// and to every path parsed from a request, so `PING`, `ping` and `Ping ` can all 
+/// reach the same controller. Defaults to exact byte matching.
+#[derive(Clone, Default)]
+pub struct MatchPolicy {
+    case_fold: bool,
+    trim: bool,
+    #[cfg(feature = "unicode")]
+    normalization: Option<Normalization>
+}
+
+impl MatchPolicy {
+    /// Paths must match byte for byte.
+    pub fn exact() -> MatchPolicy { MatchPolicy::default() }
+
+    /// Paths are compared after being lowercased.
+    pub fn case_insensitive(self) -> MatchPolicy {
+        MatchPolicy{ case_fold: true, ..self }
+    }
+
+    /// Paths are compared after removing leading and trailing whitespace.
+    pub fn trimmed(self) -> MatchPolicy {
+        MatchPolicy{ trim: true, ..self }
+    }
+
+    /// Paths are compared after being converted to the given normalization form.
+    #[cfg(feature = "unicode")]
+    pub fn normalized(self, form: Normalization) -> MatchPolicy {
+        MatchPolicy{ normalization: Some(form), ..self }
+    }
+
+    pub fn is_case_insensitive(&self) -> bool { self.case_fold }
+    pub fn is_trimmed(&self) -> bool { self.trim }
+
+    /// Transforms the path into the form used as a key in the route map.
+    pub fn apply(&self, path: &str) -> String {
+        let path = if self.trim { path.trim() } else { path };
+
+        #[cfg(feature = "unicode")]
+        let path = {
+            use unicode_normalization::UnicodeNormalization;
+
+            match self.normalization {
+                Some(Normalization::Nfc) => path.nfc().collect::<String>(),
+                Some(Normalization::Nfkc) => path.nfkc().collect::<String>(),
+                None => path.to_string(),
+            }
+        };
+
+        if self.case_fold { path.to_lowercase() } else { path.to_string() }
+    }
+
+    /// Applies the policy to a route, leaving `Route::NotFound` untouched.
+    pub(crate) fn apply_route(&self, route: Route) -> Route {
+        match route {
+            Route::Path(path) => Route::Path(self.apply(&path)),
+            other => other,
+        }
+    }
+}
+
+/// What a `RateLimit` counts requests against. Each key gets its own token bucket.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum RateKey {
+    /// Per IP address of the client.
+    Peer,
+    /// Per route matched, across every client.
+    Route,
+    /// Per IP address of the client, for each route matched.
+    PeerAndRoute
+}
+
+/// Token bucket limiting the rate of requests. Tokens are refilled evenly over the period,
+/// up to the burst size, and each request takes one token.
+/// 
+/// **DEFAULTS:**
+/// ```text
+/// burst: Same as the number of requests
+/// route: Any
+/// ```
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    pub(crate) key: RateKey,
+    pub(crate) requests: u32,
+    pub(crate) per: Duration,
+    pub(crate) burst: u32,
+    pub(crate) route: Option<Route>
+}
+
+impl RateLimit {
+    /// Allows the number of requests per period, with requests counted against the given key.
+    pub fn new(key: RateKey, requests: u32, per: Duration) -> RateLimit {
+        RateLimit{ key, requests, per, burst: requests, route: None }
+    }
+
+    pub fn per_peer(requests: u32, per: Duration) -> RateLimit { RateLimit::new(RateKey::Peer, requests, per) }
+    pub fn per_route(requests: u32, per: Duration) -> RateLimit { RateLimit::new(RateKey::Route, requests, per) }
+    pub fn per_peer_and_route(requests: u32, per: Duration) -> RateLimit { RateLimit::new(RateKey::PeerAndRoute, requests, per) }
+
+    /// Sets the most tokens a bucket can hold, allowing short bursts above the rate.
+    pub fn burst(self, burst: u32) -> RateLimit {
+        RateLimit{ burst, ..self }
+    }
+
+    /// Only applies the limit to requests matched to the given route.
+    pub fn on_route(self, route: Route) -> RateLimit {
+        RateLimit{ route: Some(route), ..self }
+    }
+
+    pub fn get_key(&self) -> RateKey { self.key }
+}
+
+/// Block of IP addresses in CIDR notation, such as `10.0.0.0/8` or `2001:db8::/32`.
+/// A bare address is parsed as a block holding only that address.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8
+}
+
+impl Cidr {
+    /// Creates a block from an address and the length of its prefix in bits.
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Cidr, ParseCidrError> {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix > max { return Err(ParseCidrError::InvalidPrefix(prefix.to_string())); }
+
+        Ok(Cidr{ addr, prefix })
+    }
+
+    /// Checks if the address is in the block. IPv4 addresses mapped to IPv6 are compared as IPv4.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(addr) & mask
+            },
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(addr) & mask
+            },
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = ParseCidrError;
+
+    fn from_str(s: &str) -> Result<Cidr, ParseCidrError> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let addr = IpAddr::from_str(addr.trim()).map_err(|_| ParseCidrError::InvalidAddress(addr.to_string()))?;
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse::<u8>().map_err(|_| ParseCidrError::InvalidPrefix(prefix.to_string()))?,
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+
+        Cidr::new(addr, prefix)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
 #[derive(Clone)]
 pub enum ParseOptions {
     Position(usize),
@@ -1,33 +1,269 @@
-use crate::{exception::InternalError, internal::Threadpool, registerable::{self, Route, DebugSetting}, cfg::{self, DefaultDebugger, RouteMap}};
+#[cfg(feature = "mio")]
+mod event;
 
-use std::{cell::{Cell, RefCell}, io::{ErrorKind, Read, Write}, net::{SocketAddr, TcpListener}, rc::Rc, sync::Arc};
+use crate::{debug::Raw, metrics::{Metrics, MetricsHandle}, exception::{InternalError, PushError, RouteError}, internal::{Threadpool, RateLimiter, SharedStream, Framer}, registerable::{self, Route, Response, DebugSetting, Level, Access, Outcome, Field}, cfg::{self, DefaultDebugger, DefaultAccessFmt}};
 
-pub struct RouteMapBuilder(RouteMap);
+use std::{cell::{Cell, RefCell}, collections::{BTreeMap, VecDeque}, io::{self, ErrorKind, Read, Write}, net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream}, ops::DerefMut, panic::{self, AssertUnwindSafe}, rc::Rc, sync::{Arc, Condvar, Mutex, RwLock, mpsc, atomic::{AtomicU32, Ordering}}, thread, time::{Duration, Instant, SystemTime}};
+
+pub struct RouteMapBuilder {
+    /// Registrations in order, each flagged on whether it intentionally overrides an earlier registration.
+    entries: Vec<(Route, Box<dyn registerable::Controller>, bool)>,
+    redactions: Vec<(Route, registerable::Redaction)>
+}
 
 impl RouteMapBuilder {
     fn new() -> RouteMapBuilder {
-        RouteMapBuilder(RouteMap::new())
+        RouteMapBuilder { entries: Vec::new(), redactions: Vec::new() }
     }
 
     /// Registers a `registerable::Controller` in the route map, with the path being used as the key to find that controller.
     /// For a client to access an endpoint, the route after being split must match the path given here. 
+    /// 
+    /// Registering a route twice replaces the earlier controller, unless the builder is set to strict routes,
+    /// in which case building fails. Use `RouteMapBuilder::replace` for intentional overrides.
     pub fn register(mut self, controller: Box<dyn registerable::Controller>, path: Route) -> RouteMapBuilder {
-        if self.0.contains_key(&path) {
-            self.0.remove(&path);
-        }
+        self.entries.push((path, controller, false));
+        self
+    }
 
-        self.0.insert(path, controller);
+    /// Registers a `registerable::Controller`, replacing any controller registered to the same route
+    /// without being reported as a conflict.
+    pub fn replace(mut self, controller: Box<dyn registerable::Controller>, path: Route) -> RouteMapBuilder {
+        self.entries.push((path, controller, true));
         self
     }
 
-    fn build(self) -> RouteMap { self.0 }
+    /// Sets how requests to the route and their responses appear in debug records,
+    /// such as hiding passwords sent to an authentication route.
+    pub fn redact(mut self, path: Route, redaction: registerable::Redaction) -> RouteMapBuilder {
+        self.redactions.push((path, redaction));
+        self
+    }
+
+    /// Consumes the builder, keying every path by its form under the given policy.
+    /// 
+    /// If strict, fails listing every route registered more than once through `RouteMapBuilder::register`,
+    /// including paths that only collide after the policy is applied. Otherwise, the last registration wins,
+    /// and every such collision is written to the debugger as a warning.
+    fn build(self, policy: &registerable::MatchPolicy, strict: bool, debug: &cfg::Debug) -> Result<cfg::Routes, RouteError> { 
+        const DEBUG_HANDLE: &str = "server::RouteMapBuilder::build";
+
+        let mut routes = cfg::Routes::default();
+        let mut conflicts = Vec::<Route>::new();
+        // Route each key was last registered as, before the policy was applied.
+        let mut registered = BTreeMap::<Route, Route>::new();
+
+        for (original, controller, replace) in self.entries {
+            let route = policy.apply_route(original.clone());
+            let earlier = registered.insert(route.clone(), original.clone());
+
+            if routes.insert(route.clone(), Arc::from(controller))?.is_none() || replace { continue }
+
+            if let Some(earlier) = earlier {
+                debug.warn(DEBUG_HANDLE, "Route registered more than once, replacing the earlier controller.", 
+                    &[("route", &route), ("earlier", &earlier), ("later", &original)]);
+            }
+
+            if !conflicts.contains(&route) { conflicts.push(route); }
+        }
+
+        if strict && !conflicts.is_empty() { return Err(RouteError::Duplicate(conflicts)); }
+
+        for (route, redaction) in self.redactions {
+            routes.redactions.insert(policy.apply_route(route), redaction);
+        }
+
+        Ok(routes)
+    }
+}
+
+/// Handle for changing the routes of a `Host`, including while it is running.
+/// Obtained through `Host::route_handle`, and can be cloned and sent across threads.
+/// 
+/// Changes apply to the next request matched. Requests already being served 
+/// finish on the controller they were matched to.
+#[derive(Clone)]
+pub struct RouteHandle(cfg::ConfigAlias);
+
+impl RouteHandle {
+    /// Registers the controller only if no controller is registered to the route.
+    pub fn add(&self, controller: Box<dyn registerable::Controller>, path: Route) -> Result<(), RouteError> {
+        let path = self.0.mp.apply_route(path);
+        let mut routes = self.0.routes.write().unwrap();
+
+        if routes.rm.contains_key(&path) { return Err(RouteError::Occupied); }
+
+        routes.insert(path, Arc::from(controller)).map(|_| ())
+    }
+
+    /// Registers the controller, replacing any controller already registered to the route.
+    /// Returns true if a controller was replaced.
+    pub fn replace(&self, controller: Box<dyn registerable::Controller>, path: Route) -> Result<bool, RouteError> {
+        let path = self.0.mp.apply_route(path);
+
+        self.0.routes.write().unwrap()
+            .insert(path, Arc::from(controller))
+            .map(|old| old.is_some())
+    }
+
+    /// Removes the controller registered to the route.
+    pub fn remove(&self, path: Route) -> Result<(), RouteError> {
+        let path = self.0.mp.apply_route(path);
+
+        match self.0.routes.write().unwrap().remove(&path) {
+            Some(_) => Ok(()),
+            None => Err(RouteError::NotRegistered),
+        }
+    }
+
+    /// Sets how requests to the registered route and their responses appear in debug records.
+    /// The redaction is cleared along with the route when it is removed.
+    pub fn redact(&self, path: Route, redaction: registerable::Redaction) -> Result<(), RouteError> {
+        let path = self.0.mp.apply_route(path);
+        let mut routes = self.0.routes.write().unwrap();
+
+        if !routes.rm.contains_key(&path) { return Err(RouteError::NotRegistered); }
+
+        routes.redactions.insert(path, redaction);
+        Ok(())
+    }
+
+    /// Clears the redaction of the route, so its requests and responses appear in debug records as they are.
+    /// Returns true if a redaction was cleared.
+    pub fn clear_redaction(&self, path: Route) -> bool {
+        let path = self.0.mp.apply_route(path);
+        self.0.routes.write().unwrap().redactions.remove(&path).is_some()
+    }
+
+    /// Lists the routes currently registered.
+    pub fn routes(&self) -> std::vec::IntoIter<registerable::RouteInfo> {
+        self.0.routes.read().unwrap().list().into_iter()
+    }
+
+    /// Checks if a controller is registered to the route.
+    pub fn contains(&self, path: Route) -> bool {
+        let path = self.0.mp.apply_route(path);
+        self.0.routes.read().unwrap().rm.contains_key(&path)
+    }
+}
+
+/// Handle for changing which addresses may connect to a `Host`, including while it is running.
+/// Obtained through `Host::filter_handle`, and can be cloned and sent across threads.
+/// 
+/// Changes apply to connections accepted afterwards. Connections already open are unaffected.
+#[derive(Clone)]
+pub struct FilterHandle(cfg::ConfigAlias);
+
+impl FilterHandle {
+    /// Allows the block to connect. Once any block is allowed, addresses outside every allowed block are denied.
+    pub fn allow(&self, cidr: registerable::Cidr) {
+        let mut filter = self.0.filter.write().unwrap();
+        if !filter.allow.contains(&cidr) { filter.allow.push(cidr); }
+    }
+
+    /// Denies the block from connecting, even if it is within an allowed block.
+    pub fn deny(&self, cidr: registerable::Cidr) {
+        let mut filter = self.0.filter.write().unwrap();
+        if !filter.deny.contains(&cidr) { filter.deny.push(cidr); }
+    }
+
+    /// Removes the block from both the allowed and denied blocks. Returns true if it was in either.
+    pub fn remove(&self, cidr: registerable::Cidr) -> bool {
+        let mut filter = self.0.filter.write().unwrap();
+        let len = filter.allow.len() + filter.deny.len();
+
+        filter.allow.retain(|c| c != &cidr);
+        filter.deny.retain(|c| c != &cidr);
+
+        len != filter.allow.len() + filter.deny.len()
+    }
+
+    /// Removes every block, allowing any address to connect.
+    pub fn clear(&self) { *self.0.filter.write().unwrap() = cfg::Filter::default(); }
+
+    pub fn allowed(&self) -> Vec<registerable::Cidr> { self.0.filter.read().unwrap().allow.clone() }
+    pub fn denied(&self) -> Vec<registerable::Cidr> { self.0.filter.read().unwrap().deny.clone() }
+
+    /// Checks if the address may connect.
+    pub fn permits(&self, addr: IpAddr) -> bool { self.0.filter.read().unwrap().permits(addr) }
+}
+
+/// Handle for pushing messages to connected clients, outside of the request/response cycle.
+/// Obtained through `Host::push_handle`, and can be cloned and sent across threads.
+/// 
+/// Connections are identified by their order number, and pushed messages are framed 
+/// with the same length prefix as responses, never interleaving with them.
+/// 
+/// A push blocked for longer than `Builder::write_timeout`, typically on a client that stopped reading, 
+/// fails and drops that client, as part of the message may already be written.
+#[derive(Clone)]
+pub struct PushHandle(cfg::ConfigAlias);
+
+impl PushHandle {
+    /// Sends the message to the connection with the order number.
+    pub fn send(&self, ordern: u64, msg: &str) -> Result<(), PushError> {
+        let stream = self.0.registry.get(ordern).ok_or(PushError::NotConnected(ordern))?;
+        let res = Host::prepend_length(msg, self.0.mrl).map_err(|_| PushError::Oversize(msg.len()))?;
+
+        stream.write(res.as_bytes()).inspect_err(|_| self.drop_client(&stream, ordern))?;
+        Ok(())
+    }
+
+    /// Sends the message to every open connection, returning how many it was written to.
+    pub fn broadcast(&self, msg: &str) -> Result<usize, PushError> {
+        let res = Host::prepend_length(msg, self.0.mrl).map_err(|_| PushError::Oversize(msg.len()))?;
+
+        Ok(self.0.registry.all().into_iter()
+            .filter(|(ordern, stream)| self.write(stream, &res, *ordern))
+            .count())
+    }
+
+    /// Writes an already framed message, returning false if it failed.
+    fn write(&self, stream: &SharedStream, res: &str, ordern: u64) -> bool {
+        match stream.write(res.as_bytes()) {
+            Ok(_) => true,
+            Err(err) => {
+                self.0.debug.warn("server::PushHandle", &format!("Failed to push message: {}", err), &[("ordern", &ordern)]);
+                self.drop_client(stream, ordern);
+                false
+            },
+        }
+    }
+
+    /// Unregisters a connection a push failed on, then ends it, so nothing more is written after a partial message.
+    fn drop_client(&self, stream: &SharedStream, ordern: u64) {
+        self.0.registry.remove(ordern);
+        self.0.topics.remove(ordern);
+        stream.abort();
+    }
+
+    /// Sends the message to every connection subscribed to the topic, returning how many it was written to.
+    pub fn publish(&self, topic: &str, msg: &str) -> Result<usize, PushError> {
+        let res = Host::prepend_length(msg, self.0.mrl).map_err(|_| PushError::Oversize(msg.len()))?;
+
+        Ok(self.0.topics.subscribers(topic).into_iter()
+            .filter_map(|ordern| self.0.registry.get(ordern).map(|stream| (ordern, stream)))
+            .filter(|(ordern, stream)| self.write(stream, &res, *ordern))
+            .count())
+    }
+
+    /// Order numbers of every connection subscribed to the topic.
+    pub fn subscribers(&self, topic: &str) -> Vec<u64> { self.0.topics.subscribers(topic) }
+
+    /// Topics with at least one subscriber.
+    pub fn topics(&self) -> Vec<String> { self.0.topics.topics() }
+
+    /// Order numbers of every open connection.
+    pub fn connections(&self) -> Vec<u64> {
+        self.0.registry.all().into_iter().map(|(ordern, _)| ordern).collect()
+    }
 }
 
 /// Builder for configuring server options. 
 /// After setting the options, call `bunker::server::Builder::build`, which will consume the Builder and return a `bunker::server::Instance`.
 /// 
 /// **DEFAULTS:**
-/// ```
+/// ```text
 /// port: 3055
 /// addr: [127, 0, 0, 1]
 /// threads: 1
@@ -38,6 +274,34 @@ impl RouteMapBuilder {
 /// debug_writer: Default Writer
 /// debug: On
 /// max_response_length: 9999
+/// match_policy: exact
+/// help_route: None
+/// health_route: None
+/// subscribe_route: None
+/// unsubscribe_route: None
+/// quit_command: None
+/// strict_routes: false
+/// access_log: Off
+/// access_fmt: Default Formatter
+/// metrics_port: None
+/// rate_limits: None
+/// rate_limited_response: "Too many requests."
+/// drop_after_violations: None
+/// allow: Any
+/// deny: None
+/// max_messages: None
+/// request_delimiter: None
+/// concurrent_pipelining: false
+/// request_ids: None
+/// request_tasks: false
+/// event_loop: false (with the "mio" feature)
+/// max_in_flight: 16
+/// max_lifetime: None
+/// goodbye_response: "Goodbye."
+/// farewell: "Closing connection..."
+/// half_close: false
+/// wait_for_close_ack: None
+/// write_timeout: 5s
 /// ```
 #[allow(dead_code)]
 pub struct Builder {
@@ -48,9 +312,36 @@ pub struct Builder {
     endconn_msg: String,
     parse_options: registerable::ParseOptions,
     debug: cfg::Debug,
+    access: cfg::AccessLog,
+    metrics_port: Option<u16>,
     rmb: RouteMapBuilder,
+    match_policy: registerable::MatchPolicy,
+    help_route: Option<String>,
+    quit_command: Option<String>,
+    health_route: Option<String>,
+    subscribe_route: Option<String>,
+    unsubscribe_route: Option<String>,
+    strict_routes: bool,
     max_response_length: usize,
-    response_on_error: String
+    response_on_error: String,
+    rate_limits: Vec<registerable::RateLimit>,
+    rate_limited_response: String,
+    drop_after_violations: Option<u32>,
+    filter: cfg::Filter,
+    max_messages: Option<u32>,
+    request_delimiter: Option<String>,
+    concurrent_pipelining: bool,
+    request_ids: Option<char>,
+    request_tasks: bool,
+    #[cfg(feature = "mio")]
+    event_loop: bool,
+    max_in_flight: usize,
+    max_lifetime: Option<Duration>,
+    goodbye_response: String,
+    farewell: Option<String>,
+    half_close: bool,
+    close_ack: Option<Duration>,
+    write_timeout: Duration
 }
 
 impl Builder {
@@ -63,13 +354,40 @@ impl Builder {
             endconn_msg: "CCONN".to_string(),
             parse_options: registerable::ParseOptions::position(1),
             debug: cfg::Debug::new(Box::new(DefaultDebugger)),
+            access: cfg::AccessLog::new(Box::new(DefaultAccessFmt)),
+            metrics_port: None,
             rmb: RouteMapBuilder::new(),
+            match_policy: registerable::MatchPolicy::exact(),
+            help_route: None,
+            quit_command: None,
+            health_route: None,
+            subscribe_route: None,
+            unsubscribe_route: None,
+            strict_routes: false,
             max_response_length: 9999,
-            response_on_error: String::new()
+            response_on_error: String::new(),
+            rate_limits: Vec::new(),
+            rate_limited_response: String::from("Too many requests."),
+            drop_after_violations: None,
+            filter: cfg::Filter::default(),
+            max_messages: None,
+            request_delimiter: None,
+            concurrent_pipelining: false,
+            request_ids: None,
+            request_tasks: false,
+            #[cfg(feature = "mio")]
+            event_loop: false,
+            max_in_flight: 16,
+            max_lifetime: None,
+            goodbye_response: String::from("Goodbye."),
+            farewell: Some(String::from("Closing connection...")),
+            half_close: false,
+            close_ack: None,
+            write_timeout: Duration::from_secs(5)
         }
     }
 
-    #[deprecated(since="0.2", note="use Builder::debugger_level_*")]
+    #[deprecated(since="0.2.0", note="use Builder::debugger_level_*")]
     /// Will stop Bunker from writing debugging information to the standard output.
     pub fn debugger_off(mut self) -> Builder {
         self.debug.off();
@@ -82,6 +400,38 @@ impl Builder {
         self
     }
 
+    /// Registers a custom implementation of `registerable::DebugSink`, which receives every formatted record.
+    /// Defaults to writing warnings and errors to stderr, and anything else to stdout.
+    pub fn set_debug_sink(mut self, sink: Box<dyn registerable::DebugSink>) -> Builder {
+        self.debug.replace_sink(sink);
+        self
+    }
+
+    /// Sets debugger to write records of the given level or more severe.
+    pub fn debugger_level(mut self, level: Level) -> Builder {
+        self.debug.level(level);
+        self
+    }
+
+    /// Turns on the access log, writing one line per request/response cycle to the given sink.
+    /// The access log is separate from the debugger, and is unaffected by its level.
+    pub fn access_log(mut self, sink: Box<dyn registerable::AccessSink>) -> Builder {
+        self.access.replace_sink(sink);
+        self
+    }
+
+    /// Registers a custom implementation of `registerable::AccessFmt` for the access log.
+    pub fn set_access_fmt(mut self, fmt: Box<dyn registerable::AccessFmt>) -> Builder {
+        self.access.replace_writer(fmt);
+        self
+    }
+
+    /// Serves the metrics in Prometheus text format over HTTP on the given port, 
+    /// bound to the same address as the server. Metrics are always available through `Host::metrics_handle`.
+    pub fn metrics_port(self, port: u16) -> Builder {
+        Builder{ metrics_port: Some(port), ..self }
+    }
+
     /// Sets debugger to never write.
     pub fn debugger_level_none(mut self) -> Builder {
         self.debug.off();
@@ -94,9 +444,10 @@ impl Builder {
         self
     }
 
-    /// Sets debugger to write errors.
+    /// Sets debugger to write anything, errors included.
+    /// To write errors only, use `Builder::debugger_level(Level::Error)`.
     pub fn debugger_level_error(mut self) -> Builder {
-        self.debug.error();
+        self.debug.coarse_error();
         self
     }
 
@@ -125,6 +476,8 @@ impl Builder {
     }
     
     /// Sets the string that will be checked for to close the connection.
+    /// 
+    /// *Kept for compatibility. Controllers should return `Response::reply_and_close` or `Response::close` instead.*
     pub fn endconn_msg(self, endconn_msg: String) -> Builder { 
         Builder{endconn_msg, ..self} 
     }
@@ -134,6 +487,123 @@ impl Builder {
         Builder{ response_on_error: error_response, ..self }
     }
 
+    /// Adds a rate limit, applied to every request along with any other rate limits.
+    /// Requests to routes reserved by bunker are never limited.
+    pub fn rate_limit(mut self, limit: registerable::RateLimit) -> Builder {
+        self.rate_limits.push(limit);
+        self
+    }
+
+    /// Sets the message sent in reply to a request exceeding a rate limit.
+    pub fn rate_limited_response(self, rate_limited_response: String) -> Builder {
+        Builder{ rate_limited_response, ..self }
+    }
+
+    /// Closes the connection once this many of its requests have exceeded a rate limit.
+    pub fn drop_after_violations(self, violations: u32) -> Builder {
+        Builder{ drop_after_violations: Some(violations), ..self }
+    }
+
+    /// Closes a connection once this many of its requests have been answered, 
+    /// so long-lived clients reconnect and rebalance across instances.
+    pub fn max_messages(self, messages: u32) -> Builder {
+        Builder{ max_messages: Some(messages), ..self }
+    }
+
+    /// Splits requests on the delimiter instead of treating every read as a single request, 
+    /// so clients can send several requests without waiting for each response. Responses are written in request order.
+    /// 
+    /// Incomplete requests are kept between reads, up to the read buffer size. Empty requests are skipped.
+    pub fn request_delimiter(self, delimiter: String) -> Builder {
+        Builder{ request_delimiter: Some(delimiter).filter(|delimiter| !delimiter.is_empty()), ..self }
+    }
+
+    /// Serves the requests split out of a single read concurrently on the threadpool, still writing responses in request order.
+    /// Requests after one that closes the connection are served, but their responses are discarded.
+    pub fn concurrent_pipelining(self, concurrent_pipelining: bool) -> Builder {
+        Builder{ concurrent_pipelining, ..self }
+    }
+
+    /// Reads an ID up to the separator at the start of every request, and echoes it with the separator ahead of every frame of its response.
+    /// As responses can then be told apart, the requests split out of a single read are served concurrently 
+    /// on the threadpool, and their responses are written as they complete rather than in request order.
+    pub fn request_ids(self, separator: char) -> Builder {
+        Builder{ request_ids: Some(separator), ..self }
+    }
+
+    /// Reads every connection on a thread of its own, submitting each request to the threadpool as a separate task,
+    /// so `threads` bounds concurrent requests rather than concurrent connections, and idle connections take no worker.
+    /// Responses are written in request order, or as they complete with request IDs.
+    /// 
+    /// Streamed responses are buffered whole before they are written in request order.
+    pub fn request_tasks(self, request_tasks: bool) -> Builder {
+        Builder{ request_tasks, ..self }
+    }
+
+    /// Handles every connection on a single readiness-based event loop, which accepts, reads and writes without blocking,
+    /// submitting only complete requests to the threadpool as separate tasks like `Builder::request_tasks`.
+    /// Idle connections take no thread at all, so `threads` bounds concurrent requests rather than concurrent connections.
+    /// 
+    /// *Requires the "mio" feature.*
+    #[cfg(feature = "mio")]
+    pub fn event_loop(self, event_loop: bool) -> Builder {
+        Builder{ event_loop, ..self }
+    }
+
+    /// Limits how many requests of a single connection are served or queued on the threadpool at once,
+    /// with `Builder::request_tasks` or `Builder::event_loop`. Nothing more is read from the connection until one of them is answered,
+    /// so a pipelining client cannot crowd out every other connection. At least one request is always allowed.
+    pub fn max_in_flight(self, max_in_flight: usize) -> Builder {
+        Builder{ max_in_flight, ..self }
+    }
+
+    /// Closes a connection once it has been open for this long, including while it is waiting for a request.
+    /// A request being served when the lifetime ends is answered first.
+    pub fn max_lifetime(self, lifetime: Duration) -> Builder {
+        Builder{ max_lifetime: Some(lifetime), ..self }
+    }
+
+    /// Sets the message sent before closing a connection that reached its max messages or max lifetime.
+    pub fn goodbye_response(self, goodbye_response: String) -> Builder {
+        Builder{ goodbye_response, ..self }
+    }
+
+    /// Sets the message sent in place of the end-connection message, or nothing if `None`.
+    pub fn farewell(self, farewell: Option<String>) -> Builder {
+        Builder{ farewell, ..self }
+    }
+
+    /// Shuts down the writing half of a connection before dropping it whenever bunker closes it, 
+    /// so clients can tell an orderly close from a crash.
+    pub fn half_close(self, half_close: bool) -> Builder {
+        Builder{ half_close, ..self }
+    }
+
+    /// After half-closing a connection, waits up to the timeout for the client to close its side before dropping it.
+    /// Also enables `Builder::half_close`.
+    pub fn wait_for_close_ack(self, timeout: Duration) -> Builder {
+        Builder{ half_close: true, close_ack: Some(timeout), ..self }
+    }
+
+    /// Fails a write to a connection that blocks for longer than the timeout, typically on a client that stopped reading,
+    /// so it never holds up pushes to other clients. A zero timeout lets writes block indefinitely.
+    pub fn write_timeout(self, write_timeout: Duration) -> Builder {
+        Builder{ write_timeout, ..self }
+    }
+
+    /// Allows the block of addresses to connect. Once any block is allowed, 
+    /// connections from addresses outside every allowed block are closed on accept.
+    pub fn allow(mut self, cidr: registerable::Cidr) -> Builder {
+        self.filter.allow.push(cidr);
+        self
+    }
+
+    /// Closes connections from the block of addresses on accept, even if it is within an allowed block.
+    pub fn deny(mut self, cidr: registerable::Cidr) -> Builder {
+        self.filter.deny.push(cidr);
+        self
+    }
+
     /// Registers a `registerable::Controller` in the route map, with the path being used as the key to find that controller.
     /// For a client to access an endpoint, the route after being split must match the path given here. 
     pub fn register(self, controller: Box<dyn registerable::Controller>, path: Route) -> Builder {
@@ -141,6 +611,24 @@ impl Builder {
         Builder{rmb, ..self}
     }
 
+    /// Registers a `registerable::Controller`, intentionally replacing any controller registered to the same route.
+    pub fn replace(self, controller: Box<dyn registerable::Controller>, path: Route) -> Builder {
+        let rmb = self.rmb.replace(controller, path);
+        Builder{rmb, ..self}
+    }
+
+    /// Sets how requests to the route and their responses appear in debug records.
+    pub fn redact(self, path: Route, redaction: registerable::Redaction) -> Builder {
+        let rmb = self.rmb.redact(path, redaction);
+        Builder{rmb, ..self}
+    }
+
+    /// Makes registering a route more than once through `register` an error when building,
+    /// rather than silently replacing the earlier controller.
+    pub fn strict_routes(self) -> Builder {
+        Builder{ strict_routes: true, ..self }
+    }
+
     pub fn configure_routes<F>(self, f: F) -> Builder
         where 
             F : FnOnce(RouteMapBuilder) -> RouteMapBuilder + 'static
@@ -151,8 +639,8 @@ impl Builder {
 
     /// Configures the server to split incoming messages at the first instance of a character matching one of the given separators.
     /// The first string will be used as the path to pass the second string down to any matching controllers. 
-    pub fn parse_separator(self, separator: &Vec<char>) -> Builder { 
-        Builder{ parse_options: registerable::ParseOptions::separator(separator.clone()), ..self } 
+    pub fn parse_separator(self, separator: &[char]) -> Builder { 
+        Builder{ parse_options: registerable::ParseOptions::separator(separator.to_vec()), ..self } 
     }
 
     /// Configures the server to split incoming messages at the given position.
@@ -161,9 +649,50 @@ impl Builder {
         Builder{ parse_options: registerable::ParseOptions::position(position), ..self } 
     }
 
+    /// Sets the policy used to compare paths, applied both to registered routes and incoming requests.
+    pub fn match_policy(self, match_policy: registerable::MatchPolicy) -> Builder {
+        Builder{ match_policy, ..self }
+    }
+
+    /// Reserves the path for a route handled by bunker, which replies with every registered route,
+    /// one per line, with the controller's type name and description separated by tabs.
+    pub fn help_route(self, path: String) -> Builder {
+        Builder{ help_route: Some(path), ..self }
+    }
+
+    /// Reserves the path for a route handled by bunker, which replies with the status of the server
+    /// for liveness probes, as space separated `key=value` pairs: `status`, `uptime_s`, `connections_active`,
+    /// `workers_busy`, `workers` and `utilization`.
+    pub fn health_route(self, path: String) -> Builder {
+        Builder{ health_route: Some(path), ..self }
+    }
+
+    /// Reserves the path for a route handled by bunker, which subscribes the connection to the topic named by the message.
+    /// Messages published to the topic through `PushHandle::publish` are then pushed to it, until it unsubscribes or closes.
+    pub fn subscribe_route(self, path: String) -> Builder {
+        Builder{ subscribe_route: Some(path), ..self }
+    }
+
+    /// Reserves the path for a route handled by bunker, which unsubscribes the connection from the topic named by the message.
+    pub fn unsubscribe_route(self, path: String) -> Builder {
+        Builder{ unsubscribe_route: Some(path), ..self }
+    }
+
+    /// Sets a command, compared against the whole request under the match policy before routing, 
+    /// with which clients close the connection. It is answered with the farewell message.
+    pub fn quit_command(self, command: String) -> Builder {
+        Builder{ quit_command: Some(command), ..self }
+    }
+
     /// Converts the builder into a `server::Config`, for creating an Instance.
-    fn create_cfg(self) -> cfg::ConfigAlias {
-        Arc::new(cfg::Config {
+    fn create_cfg(self) -> Result<cfg::ConfigAlias, RouteError> {
+        let limiter = RateLimiter::new(self.rate_limits.into_iter()
+            .map(|limit| registerable::RateLimit{ route: limit.route.map(|route| self.match_policy.apply_route(route)), ..limit })
+            .collect());
+
+        let routes = self.rmb.build(&self.match_policy, self.strict_routes, &self.debug)?;
+
+        Ok(Arc::new(cfg::Config {
             port: self.port, 
             addr: self.addr,
             threads: self.threads, 
@@ -171,19 +700,133 @@ impl Builder {
             endconn_msg: self.endconn_msg, 
             parse_options: self.parse_options,
             debug: self.debug,
-            rm: self.rmb.build(),
+            access: self.access,
+            metrics: Arc::new(Metrics::new()),
+            metrics_port: self.metrics_port,
+            routes: RwLock::new(routes),
+            quit: self.quit_command.map(|command| self.match_policy.apply(&command)),
+            help: self.help_route.map(|path| self.match_policy.apply_route(Route::Path(path))),
+            health: self.health_route.map(|path| self.match_policy.apply_route(Route::Path(path))),
+            subscribe: self.subscribe_route.map(|path| self.match_policy.apply_route(Route::Path(path))),
+            unsubscribe: self.unsubscribe_route.map(|path| self.match_policy.apply_route(Route::Path(path))),
+            mp: self.match_policy,
             mrl: self.max_response_length,
-            er: self.response_on_error
-        })
+            er: self.response_on_error,
+            limiter,
+            rl_response: self.rate_limited_response,
+            rl_drop: self.drop_after_violations,
+            filter: RwLock::new(self.filter),
+            registry: Default::default(),
+            topics: Default::default(),
+            max_messages: self.max_messages,
+            delimiter: self.request_delimiter,
+            concurrent_pipelining: self.concurrent_pipelining,
+            id_separator: self.request_ids,
+            request_tasks: self.request_tasks,
+            #[cfg(feature = "mio")]
+            event_loop: self.event_loop,
+            max_in_flight: self.max_in_flight.max(1),
+            spawner: Default::default(),
+            max_lifetime: self.max_lifetime,
+            goodbye: self.goodbye_response,
+            farewell: self.farewell,
+            half_close: self.half_close,
+            close_ack: self.close_ack,
+            write_timeout: self.write_timeout
+        }))
     }
     
     /// Consumes the Builder and a RouteMapBuilder to construct an Instance. After the Instance is created, call `server::Host::run` to start the server.
+    /// 
+    /// # Panics
+    /// 
+    /// If the pattern of a `Route::Regex` is invalid, or if a route was registered more than once with strict routes set.
+    /// Use `Builder::try_build` to handle these as errors instead.
     pub fn build(self) -> Host
     { 
-        Host::new(self.create_cfg())
+        match self.try_build() {
+            Ok(host) => host,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Same as `Builder::build`, but returns an error if the pattern of a `Route::Regex` is invalid,
+    /// or if a route was registered more than once with strict routes set.
+    pub fn try_build(self) -> Result<Host, RouteError> {
+        Ok(Host::new(self.create_cfg()?))
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self { Builder::new() }
+}
+
+/// State of a single connection, kept for as long as it is served.
+struct Connection {
+    ordern: u64,
+    peer: Option<SocketAddr>,
+    /// Address of the client, attached to every record of this connection along with the order number.
+    peer_str: String,
+    /// Requests turned away by a rate limit.
+    violations: AtomicU32,
+    opened: Instant,
+    /// Writing half of the stream, shared with the registry for pushed messages.
+    out: SharedStream
+}
+
+impl Connection {
+    fn new(ordern: u64, peer: Option<SocketAddr>, out: SharedStream) -> Connection {
+        let peer_str = peer.map_or(String::from("unknown"), |addr| addr.to_string());
+        Connection{ ordern, peer, peer_str, violations: AtomicU32::new(0), opened: Instant::now(), out }
+    }
+
+    fn fields(&self) -> [Field<'_>; 2] {
+        [("ordern", &self.ordern), ("peer", &self.peer_str)]
     }
 }
 
+/// Unregisters a connection and counts it as closed once dropped, including when the thread serving it panics.
+struct OpenConnection<'a>(&'a cfg::Config, u64);
+
+impl Drop for OpenConnection<'_> {
+    fn drop(&mut self) {
+        self.0.registry.remove(self.1);
+        self.0.topics.remove(self.1);
+        self.0.metrics.connection_closed();
+    }
+}
+
+/// Progress of the requests of a connection served as separate tasks, shared by its reader and the tasks.
+#[derive(Default)]
+struct Pipeline {
+    /// Sequence number of the next request submitted.
+    submitted: u64,
+    /// Sequence number of the next response written in request order.
+    written: u64,
+    /// Responses served ahead of an earlier request, waiting for their turn.
+    pending: BTreeMap<u64, (io::Result<bool>, Vec<u8>)>,
+    in_flight: usize,
+    /// Requests answered, counted against the max messages.
+    messages: u32,
+    /// Set once the connection is closing, with whether bunker is the one closing it. Responses are discarded from then on.
+    closed: Option<bool>
+}
+
+/// How often the reader of a connection with requests in flight wakes to check whether one of them closed the connection.
+const TASK_POLL: Duration = Duration::from_millis(50);
+
+/// Unframed response to a single request, along with how it was produced.
+struct Reply {
+    /// Nothing is written if the controller chose not to reply.
+    body: Option<String>,
+    /// Written in place of the body if the controller streamed its response.
+    chunks: Option<registerable::Chunks>,
+    /// Closes the connection once the response is written.
+    end: bool,
+    outcome: Outcome,
+    matched: Option<Route>
+}
+
 /// A multi-threaded server. All fields are immutable 
 /// from the Host's creation and onwards.
 /// Can only be created through `server::Builder`. 
@@ -191,14 +834,18 @@ impl Builder {
 pub struct Host {
     threadpool: Threadpool,
     cfg: cfg::ConfigAlias,
-    ordern: Rc<Cell<u64>>,
+    ordern: Cell<u64>,
 }
 
 impl Host {
     fn new(cfg: cfg::ConfigAlias) -> Host {
+        let threadpool = Threadpool::new(cfg.threads);
+        cfg.metrics.attach_pool(threadpool.get_stats());
+        let _ = cfg.spawner.set(threadpool.spawner());
+
         Host{ 
-            threadpool: Threadpool::new(cfg.threads), 
-            ordern: Rc::new(Cell::new(0)),
+            threadpool, 
+            ordern: Cell::new(0),
             cfg
         }
     }
@@ -210,16 +857,36 @@ impl Host {
         self.cfg.parse_options.clone()
     }
     pub fn get_endconn_msg(&self) -> &str { &self.cfg.endconn_msg }
+    pub fn get_match_policy(&self) -> registerable::MatchPolicy { self.cfg.mp.clone() }
+
+    /// Lists the routes currently registered.
+    pub fn routes(&self) -> std::vec::IntoIter<registerable::RouteInfo> { self.route_handle().routes() }
+
+    /// Returns a handle for reading the metrics of the server, which stays valid after `Host::run` is called.
+    pub fn metrics_handle(&self) -> MetricsHandle { MetricsHandle::new(Arc::clone(&self.cfg.metrics)) }
+
+    /// Returns a handle for pushing messages to connected clients, which stays valid after `Host::run` is called.
+    pub fn push_handle(&self) -> PushHandle { PushHandle(Arc::clone(&self.cfg)) }
+
+    /// Returns a handle for changing which addresses may connect, which stays valid after `Host::run` is called.
+    pub fn filter_handle(&self) -> FilterHandle { FilterHandle(Arc::clone(&self.cfg)) }
+
+    /// Returns a handle for adding, replacing or removing controllers, which stays valid after `Host::run` is called.
+    pub fn route_handle(&self) -> RouteHandle { RouteHandle(Arc::clone(&self.cfg)) }
     
     pub fn get_debugger_level(&self) -> DebugSetting { self.cfg.debug.get_setting() }
+    /// Returns the least severe level written by the debugger, or `None` if it is off.
+    pub fn get_debugger_min_level(&self) -> Option<Level> { self.cfg.debug.get_level() }
 
-    #[deprecated(since="0.2", note="use get_debugger_level instead and compare variants")]
+    #[deprecated(since="0.2.0", note="use get_debugger_level instead and compare variants")]
     pub fn is_debugger_on(&self) -> bool { self.cfg.debug.is_state(DebugSetting::Standard) }
 
     /// Initializes the TCP socket server, binding to the assigned port, 
     /// and starts listening for connections. Once a connection is found,
     /// the stream is passed onto a new thread and the request/response cycle
-    /// starts.
+    /// starts. With `Builder::request_tasks`, that thread only reads requests,
+    /// each of which is served on the threadpool. With `Builder::event_loop`,
+    /// every connection is read and written on the calling thread instead.
     /// 
     /// The request is parsed and passed down to any matching controllers 
     /// according to the given options and route map set in the builder. 
@@ -227,142 +894,771 @@ impl Host {
     /// client ends the connection or a shutdown message is received from the 
     /// controller.
     /// 
-    /// If the debugger is set to on, debugging messages will be printed
+    /// If the debugger is set to on, debugging records will be written
     /// on initialization, and during communication with clients where
-    /// the order number for that connection is appended for identification.
+    /// the order number and peer address for that connection are attached as fields.
     pub fn run(self) {
         const DEBUG_HANDLE: &str = "server::Host::run";
         
-        self.cfg.debug.write(DEBUG_HANDLE, "Server initialized.");
+        self.cfg.debug.info(DEBUG_HANDLE, "Server initialized.", &[]);
+        self.cfg.metrics.start();
 
         let cfg = Arc::clone(&self.cfg);
 
         let sock_addr = SocketAddr::from((cfg.addr, cfg.port));
         let listener = TcpListener::bind(sock_addr).unwrap();
 
+        if let Some(port) = cfg.metrics_port {
+            let handle = self.metrics_handle();
+            let metrics_listener = TcpListener::bind(SocketAddr::from((cfg.addr, port))).unwrap();
+            let cfg = Arc::clone(&cfg);
+
+            thread::spawn(move || if let Err(err) = handle.expose(metrics_listener) {
+                cfg.debug.error(DEBUG_HANDLE, &format!("Metrics endpoint stopped: {}", err), &[]);
+            });
+        }
+
+        #[cfg(feature = "mio")]
+        if cfg.event_loop {
+            if let Err(err) = event::run(&self, listener) {
+                cfg.debug.error(DEBUG_HANDLE, &format!("Event loop stopped: {}", err), &[]);
+            }
+
+            self.cfg.debug.info(DEBUG_HANDLE, "Shutting down server...", &[]);
+            return;
+        }
+
         for stream in listener.incoming() {
             match stream {
-                Ok(mut stream) => {
+                Ok(stream) => {
                     let cfg = Arc::clone(&cfg);
 
-                    // Increments original order number, then clones copy.
-                    // No need for atomic as number only changes in single-threaded context.
-                    Rc::clone(&self.ordern)
-                        .set(Rc::clone(&self.ordern).get() + 1); 
-                    let ordern_copy = Rc::clone(&self.ordern).get();
+                    let peer = stream.peer_addr().ok();
+                    let Some(ordern) = self.admit(peer) else { continue }; // Dropping the stream closes the connection.
                     
-                    // String indicating origin, with a number corresponding to the open connection thats being served.
-                    let local_debug_handle = format!("{}::ordern({})", DEBUG_HANDLE, ordern_copy);
+                    // The writing half is registered, so messages can be pushed to the client while its worker waits for requests.
+                    // Writes time out, so a client that stopped reading never holds up pushes to others.
+                    let out = match stream.try_clone().and_then(|out| out.set_write_timeout(Some(cfg.write_timeout).filter(|timeout| !timeout.is_zero())).map(|_| out)) {
+                        Ok(out) => SharedStream::new(out),
+                        Err(err) => {
+                            cfg.debug.warn(DEBUG_HANDLE, &format!("Failed to clone stream: {}", err), &[("ordern", &ordern)]);
+                            continue;
+                        },
+                    };
+                    cfg.registry.insert(ordern, out.clone());
 
-                    cfg.debug.write(&local_debug_handle, "Connection initiated.");
+                    let conn = Connection::new(ordern, peer, out);
 
-                    self.threadpool.execute(move|| {
+                    cfg.debug.info(DEBUG_HANDLE, "Connection initiated.", &conn.fields());
+                    cfg.metrics.connection_accepted();
 
-                        // Buffer for data received from client.
-                        let mut buff = vec![0 as u8; cfg.read_buffer_size];
-                    
-                        let mut end = false;
-
-                        loop {
-                            let size = stream.read(&mut buff[..]).unwrap();
-
-                            cfg.debug.write(&local_debug_handle, 
-                                &format!("(size: {}) Raw incoming data: {:?}", size, &buff[0..size]));
-
-                            let req = String::from_utf8_lossy(&buff[0..size]);
-                            let req = req.trim(); // Removes whitespace for whitespace-sensitive parsing options.
-
-                            cfg.debug.write(&local_debug_handle, 
-                                &format!("Received message: {}", req));
-
-                            let error_b = Rc::new(RefCell::new(String::new()));
-
-                            let mut res: String = {
-                                
-                                // Parses data according to which flag is set.
-                                let (path, msg) = match &cfg.parse_options {
-                                    registerable::ParseOptions::Position(pos) => {
-                                        let (path, msg) = req.split_at(*pos);
-                                        cfg.debug.write(
-                                            &local_debug_handle,
-                                            &format!(
-                                                "Parsed message with the given position ({})!\nPath: {}\nMessage: {}", 
-                                                pos, 
-                                                path, 
-                                                msg
-                                            )
-                                        );
-
-                                        (Route::Path(path.to_string()), msg)
-                                    },
-                                    registerable::ParseOptions::Separators(chars) => {
-                                        match req.split_once(&chars[..]) {
-                                            Some((path, msg)) => (Route::Path(path.to_string()), msg),
-                                            None => (Route::NotFound, req),
-                                        }
-                                    },
-                                };
-                                
-                                // Matches the result of the parse.
-                                if let Some(controller) = cfg.rm.get(&path) {
-                                        controller.serve(msg.to_string(), Rc::clone(&error_b))
-                                } else { 
-
-                                    if let Some(controller) = cfg.rm.get(&Route::NotFound) {
-                                        controller.serve(msg.to_string(), Rc::clone(&error_b))
-                                    } else {
-                                        // Error results from the path not matching any key in the map.
-                                        let err = InternalError::NoControllerFound(ordern_copy);
-                                        error_b.replace(err.to_string());
-                                        cfg.er.to_owned()
-                                    }
-                                }
-                            };
-
-                            let error = error_b.take();
-
-                            if !error.is_empty() {
-                                cfg.debug.write_err(&local_debug_handle, &error);
-                                res = cfg.er.to_owned();
-                            }
-
-                            if res == cfg.endconn_msg { 
-                                
-                                cfg.debug.write(
-                                    &local_debug_handle, 
-                                    "Controller ending connection."
-                                );
-
-                                res = String::from("Closing connection...");
-                                end = true;
-                            } // Writes confirmation for closing the connection and signals to break;
-
-                            // Prepend length of message to response according to mrl
-                            let res = Host::prepend_length(&res, cfg.mrl).unwrap();
-
-                            // Writes to the stream and then handles buffers.
-                            cfg.debug.write(&local_debug_handle, &format!("Writing response: {}", res));
-
-                            stream.write(res.as_bytes()).unwrap();
-                            stream.flush().unwrap();
-                            buff.flush().unwrap();
-
-                            if end { break }
-                        }
-                    
-                        cfg.debug.write(&local_debug_handle, "Closing connection.");
-                    })
+                    if !cfg.request_tasks {
+                        self.threadpool.execute(move|| Host::serve_connection(cfg, stream, conn));
+                        continue;
+                    }
+
+                    let spawned = thread::Builder::new()
+                        .name(format!("bunker-conn-{}", ordern))
+                        .spawn({ let cfg = Arc::clone(&cfg); move|| Host::read_connection(cfg, stream, conn) });
+
+                    if let Err(err) = spawned {
+                        cfg.debug.error(DEBUG_HANDLE, &format!("Failed to spawn connection reader: {}", err), &[("ordern", &ordern)]);
+                        cfg.registry.remove(ordern);
+                        cfg.metrics.connection_closed();
+                    }
                 },
                 Err(err) => {
                     match err.kind() {
                         ErrorKind::Interrupted => continue,
-                        _ => { println!("UNEXPECTED ERROR - {:?}", err) }
+                        _ => cfg.debug.warn(DEBUG_HANDLE, &format!("Failed to accept connection: {}", err), &[])
                     }
                 }
             }
         }
         
-        self.cfg.debug.write(DEBUG_HANDLE, "Shutting down server...");
+        self.cfg.debug.info(DEBUG_HANDLE, "Shutting down server...", &[]);
+    }
+
+    /// Checks the address of a new connection against the filter, returning its order number if it is permitted.
+    fn admit(&self, peer: Option<SocketAddr>) -> Option<u64> {
+        const DEBUG_HANDLE: &str = "server::Host::admit";
+
+        // Connections from addresses that cannot be determined are only accepted without a filter.
+        let permitted = match peer {
+            Some(addr) => self.cfg.filter.read().unwrap().permits(addr.ip()),
+            None => { let filter = self.cfg.filter.read().unwrap(); filter.allow.is_empty() && filter.deny.is_empty() },
+        };
+
+        if !permitted {
+            let peer = peer.map_or(String::from("unknown"), |addr| addr.to_string());
+            self.cfg.debug.warn(DEBUG_HANDLE, "Connection denied.", &[("peer", &peer)]);
+            self.cfg.metrics.connection_denied();
+            return None;
+        }
+
+        // Increments original order number, then copies it.
+        // No need for atomic as number only changes in single-threaded context.
+        self.ordern.set(self.ordern.get() + 1); 
+        Some(self.ordern.get())
+    }
+
+    /// Runs the request/response cycle of a single connection, until either side ends it.
+    fn serve_connection(cfg: cfg::ConfigAlias, mut stream: TcpStream, conn: Connection) {
+        const DEBUG_HANDLE: &str = "server::Host::serve_connection";
+
+        let _open = OpenConnection(&cfg, conn.ordern);
+
+        // Shared with the pool when requests are served concurrently.
+        let conn = Arc::new(conn);
+
+        // Buffer for data received from client.
+        let mut buff = vec![0_u8; cfg.read_buffer_size];
+        let mut framer = cfg.delimiter.as_ref().map(|delimiter| Framer::new(delimiter, cfg.read_buffer_size));
+
+        // Requests answered, counted against the max messages.
+        let mut messages = 0;
+
+        // Whether bunker is the one closing the connection, rather than the client or an error.
+        let closing = 'conn: loop {
+            let requests = match Host::read_requests(&cfg, &mut stream, &conn, &mut buff, &mut framer, None) {
+                Ok(requests) => requests,
+                Err(Some(limit)) => {
+                    Host::say_goodbye(&cfg, &conn, limit);
+                    break true;
+                },
+                Err(None) => break false,
+            };
+
+            // With request IDs, responses are written as they complete rather than in request order.
+            let served = if (cfg.id_separator.is_some() || cfg.concurrent_pipelining) && requests.len() > 1 {
+                Host::serve_concurrently(&cfg, &conn, requests, cfg.id_separator.is_none())
+            } else {
+                requests.into_iter()
+                    .map(|req| Host::serve_request(&cfg, &conn, &req, || conn.out.lock()))
+                    .collect()
+            };
+
+            // Results are handled in request order. Anything after a request ending the connection is discarded.
+            for served in served {
+                match served {
+                    Ok(true) => break 'conn true,
+                    Ok(false) => (),
+                    Err(err) => {
+                        cfg.debug.warn(DEBUG_HANDLE, &format!("Failed to write response: {}", err), &conn.fields());
+                        break 'conn false;
+                    },
+                }
+
+                messages += 1;
+                if cfg.max_messages.is_some_and(|max| messages >= max) {
+                    Host::say_goodbye(&cfg, &conn, "max_messages");
+                    break 'conn true;
+                }
+            }
+        };
+
+        Host::end_connection(&cfg, &mut stream, &conn, closing);
+    }
+
+    /// Reads requests from a single connection on its own thread, submitting each to the pool as a separate task, until either side ends it.
+    /// Responses are written in request order, or as they complete with request IDs.
+    fn read_connection(cfg: cfg::ConfigAlias, mut stream: TcpStream, conn: Connection) {
+        let _open = OpenConnection(&cfg, conn.ordern);
+        let conn = Arc::new(conn);
+        let pipeline: Arc<(Mutex<Pipeline>, Condvar)> = Default::default();
+
+        let mut buff = vec![0_u8; cfg.read_buffer_size];
+        let mut framer = cfg.delimiter.as_ref().map(|delimiter| Framer::new(delimiter, cfg.read_buffer_size));
+
+        let closing = loop {
+            // Only a task in flight can close the connection while the reader waits.
+            let state = pipeline.0.lock().unwrap();
+            if let Some(closing) = state.closed { break closing }
+            let poll = (state.in_flight > 0).then_some(TASK_POLL);
+            drop(state);
+
+            let read = Host::read_requests(&cfg, &mut stream, &conn, &mut buff, &mut framer, poll);
+            let mut state = pipeline.0.lock().unwrap();
+
+            if let Some(closing) = state.closed { break closing }
+
+            let requests = match read {
+                Ok(requests) => requests,
+                Err(Some(limit)) => {
+                    // Requests being served are answered before the goodbye, unless one of them closes the connection first.
+                    let mut state = pipeline.1.wait_while(state, |state| state.in_flight > 0).unwrap();
+                    if let Some(closing) = state.closed { break closing }
+
+                    Host::say_goodbye(&cfg, &conn, limit);
+                    state.closed = Some(true);
+                    break true;
+                },
+                Err(None) => break false,
+            };
+
+            for req in requests {
+                // Nothing more is submitted while the connection has as many tasks as it may, or once one of them closes it.
+                state = pipeline.1.wait_while(state, |state| state.in_flight >= cfg.max_in_flight && state.closed.is_none()).unwrap();
+                if state.closed.is_some() { break }
+
+                Host::submit(&cfg, &conn, &pipeline, &mut state, req);
+            }
+        };
+
+        // Requests already submitted are answered, unless bunker is closing the connection, before it ends.
+        drop(pipeline.1.wait_while(pipeline.0.lock().unwrap(), |state| state.in_flight > 0).unwrap());
+        Host::end_connection(&cfg, &mut stream, &conn, closing);
+    }
+
+    /// Submits a request to the pool as a separate task, numbered in the order received.
+    fn submit(cfg: &cfg::ConfigAlias, conn: &Arc<Connection>, pipeline: &Arc<(Mutex<Pipeline>, Condvar)>, state: &mut Pipeline, req: Vec<u8>) {
+        // Attached when the host is created, before any connection is accepted.
+        let spawner = cfg.spawner.get().unwrap();
+
+        let seq = state.submitted;
+        state.submitted += 1;
+        state.in_flight += 1;
+
+        let (cfg, conn, pipeline) = (Arc::clone(cfg), Arc::clone(conn), Arc::clone(pipeline));
+        spawner.execute(move|| Host::serve_task(&cfg, &conn, &pipeline, seq, &req));
+    }
+
+    /// Serves a single request submitted by `Host::submit`, writing its response once every earlier response has been written,
+    /// or right away with request IDs.
+    fn serve_task(cfg: &cfg::Config, conn: &Connection, pipeline: &(Mutex<Pipeline>, Condvar), seq: u64, req: &[u8]) {
+        let ordered = cfg.id_separator.is_none();
+        let closed = pipeline.0.lock().unwrap().closed.is_some();
+
+        let mut res = Vec::new();
+
+        // The task is still counted out if the controller panics, so later responses are not held back.
+        let served = Host::serve_caught(cfg, conn, || match (closed, ordered) {
+            (true, _) => Ok(false), // Nothing is served once the connection is closing.
+            (false, true) => Host::serve_request(cfg, conn, req, || &mut res),
+            (false, false) => Host::serve_request(cfg, conn, req, || conn.out.lock()),
+        });
+
+        let mut state = pipeline.0.lock().unwrap();
+
+        if ordered {
+            state.pending.insert(seq, (served, res));
+
+            loop {
+                let next = state.written;
+                let Some((served, res)) = state.pending.remove(&next) else { break };
+
+                state.written += 1;
+                Host::finish_task(cfg, conn, &mut state, served, Some(&res));
+            }
+        } else {
+            Host::finish_task(cfg, conn, &mut state, served, None);
+        }
+
+        state.in_flight -= 1;
+        pipeline.1.notify_all();
+        drop(state);
+
+        // Lets the event loop owning the connection, if any, close it once nothing is left in flight.
+        conn.out.wake();
+    }
+
+    /// Writes a buffered response if any, then closes the connection if the request or its limits call for it.
+    fn finish_task(cfg: &cfg::Config, conn: &Connection, state: &mut Pipeline, served: io::Result<bool>, res: Option<&[u8]>) {
+        const DEBUG_HANDLE: &str = "server::Host::finish_task";
+
+        if state.closed.is_some() { return }
+
+        let served = match res {
+            Some(res) => served.and_then(|end| conn.out.write(res).map(|_| end)),
+            None => served,
+        };
+
+        let closing = match served {
+            Ok(true) => true,
+            Ok(false) => {
+                state.messages += 1;
+                if cfg.max_messages.is_none_or(|max| state.messages < max) { return }
+
+                Host::say_goodbye(cfg, conn, "max_messages");
+                true
+            },
+            Err(err) => {
+                cfg.debug.warn(DEBUG_HANDLE, &format!("Failed to write response: {}", err), &conn.fields());
+                false
+            },
+        };
+
+        state.closed = Some(closing);
+    }
+
+    /// Waits for the next read from the client, bounded by whatever remains of the max lifetime, returning the requests it completes.
+    /// If the connection has ended, returns the limit it reached if any.
+    /// 
+    /// With a poll interval, returns no requests whenever it passes without a read, so the caller can check on the connection.
+    fn read_requests(cfg: &cfg::Config, stream: &mut TcpStream, conn: &Connection, buff: &mut [u8], framer: &mut Option<Framer>, poll: Option<Duration>) -> Result<Vec<Vec<u8>>, Option<&'static str>> {
+        const DEBUG_HANDLE: &str = "server::Host::read_requests";
+
+        loop {
+            let remaining = cfg.max_lifetime.map(|max| max.saturating_sub(conn.opened.elapsed()));
+            if remaining.is_some_and(|remaining| remaining.is_zero()) { return Err(Some("max_lifetime")) }
+
+            // Also cleared when neither applies, as an earlier poll may have set it.
+            let timeout = match (remaining, poll) {
+                (Some(remaining), Some(poll)) => Some(remaining.min(poll)),
+                (remaining, poll) => remaining.or(poll),
+            };
+            if stream.set_read_timeout(timeout).is_err() { return Err(Some("max_lifetime")) }
+
+            let size = match stream.read(buff) {
+                Ok(0) => return Err(None), // Client ended the connection.
+                Ok(size) => size,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) && timeout.is_some() => {
+                    if poll.is_some() && cfg.max_lifetime.is_none_or(|max| conn.opened.elapsed() < max) { return Ok(Vec::new()) }
+                    return Err(Some("max_lifetime"));
+                },
+                Err(err) => {
+                    cfg.debug.warn(DEBUG_HANDLE, &format!("Failed to read request: {}", err), &conn.fields());
+                    return Err(None);
+                },
+            };
+
+            // Without a delimiter, every read is a single request.
+            return Ok(match framer {
+                Some(framer) => framer.push(&buff[0..size]),
+                None => vec![buff[0..size].to_vec()],
+            });
+        }
+    }
+
+    /// Unregisters the connection, then half-closes it if bunker is the one closing it.
+    /// The connection is counted as closed once its `OpenConnection` is dropped.
+    fn end_connection(cfg: &cfg::Config, stream: &mut TcpStream, conn: &Connection, closing: bool) {
+        const DEBUG_HANDLE: &str = "server::Host::end_connection";
+
+        // Unregistered first, so nothing is pushed after the last response.
+        cfg.registry.remove(conn.ordern);
+        cfg.topics.remove(conn.ordern);
+        if closing { Host::close(cfg, stream, conn); }
+    
+        cfg.debug.info(DEBUG_HANDLE, "Closing connection.", &conn.fields());
+    }
+
+    /// Serves a single request, writing its response to the output given once the controller has returned. 
+    /// Returns whether the connection should be closed.
+    fn serve_request<W, G, F>(cfg: &cfg::Config, conn: &Connection, data: &[u8], out: F) -> io::Result<bool> 
+        where
+            W: Write,
+            G: DerefMut<Target = W>,
+            F: FnOnce() -> G
+    {
+        let received = (SystemTime::now(), Instant::now());
+
+        let (tag, req) = Host::split_id(cfg, data);
+        let reply = Host::respond(cfg, conn, req);
+        let mut outcome = reply.outcome;
+
+        let mut out = out();
+        let written = match reply.chunks {
+            Some(chunks) => Host::write_stream(cfg, conn, &tag, chunks, &mut outcome, &mut *out)?,
+            None => Host::write_reply(cfg, conn, &tag, reply.body.as_deref(), &mut outcome, &mut *out)?,
+        };
+        out.flush()?;
+        drop(out);
+
+        let latency = received.1.elapsed();
+        cfg.metrics.request(reply.matched.as_ref().map(|route| route.to_string()), outcome, written, latency);
+
+        if cfg.access.is_on() {
+            cfg.access.write(&Access {
+                time: received.0,
+                peer: &conn.peer_str,
+                ordern: conn.ordern,
+                route: reply.matched.as_ref(),
+                request_bytes: data.len(),
+                response_bytes: written,
+                latency,
+                outcome
+            });
+        }
+
+        Ok(reply.end)
+    }
+
+    /// Runs a request being served, failing it if its controller panics, which closes the connection there.
+    fn serve_caught<F: FnOnce() -> io::Result<bool>>(cfg: &cfg::Config, conn: &Connection, serve: F) -> io::Result<bool> {
+        const DEBUG_HANDLE: &str = "server::Host::serve_caught";
+
+        panic::catch_unwind(AssertUnwindSafe(serve)).unwrap_or_else(|_| {
+            cfg.debug.error(DEBUG_HANDLE, "Request panicked while being served.", &conn.fields());
+            Err(io::Error::other("request panicked"))
+        })
+    }
+
+    /// Serves requests split out of a single read concurrently on the pool, returning once all of them are served. 
+    /// 
+    /// If ordered, responses are buffered to write them in request order, and nothing is written after a request closing the connection. 
+    /// Otherwise, each response is written as soon as it is served.
+    /// 
+    /// The connection's own worker serves any request no other worker has picked up yet, so it never waits on the queue.
+    fn serve_concurrently(cfg: &cfg::ConfigAlias, conn: &Arc<Connection>, requests: Vec<Vec<u8>>, ordered: bool) -> Vec<io::Result<bool>> {
+        let count = requests.len();
+        let queue = Arc::new(Mutex::new(requests.into_iter().enumerate().collect::<VecDeque<_>>()));
+        let (tx, rx) = mpsc::channel();
+
+        let work = {
+            let (cfg, conn, queue) = (Arc::clone(cfg), Arc::clone(conn), Arc::clone(&queue));
+            move |tx: mpsc::Sender<_>| loop {
+                let Some((n, req)) = queue.lock().unwrap().pop_front() else { break };
+
+                let mut res = Vec::new();
+                let served = Host::serve_caught(&cfg, &conn, || if ordered {
+                    Host::serve_request(&cfg, &conn, &req, || &mut res)
+                } else {
+                    Host::serve_request(&cfg, &conn, &req, || conn.out.lock())
+                });
+                let _ = tx.send((n, served, res));
+            }
+        };
+
+        if let Some(spawner) = cfg.spawner.get() {
+            for _ in 1..count {
+                let (work, tx) = (work.clone(), tx.clone());
+                spawner.execute(move|| work(tx));
+            }
+        }
+        work(tx);
+
+        // Requests not served by this worker are already being served by another, so every result arrives.
+        let mut results: Vec<_> = rx.iter().take(count).collect();
+        results.sort_by_key(|(n, _, _)| *n);
+
+        let mut written = Vec::with_capacity(count);
+        for (_, served, res) in results {
+            let served = if ordered { served.and_then(|end| conn.out.write(&res).map(|_| end)) } else { served };
+            let last = !matches!(served, Ok(false));
+
+            written.push(served);
+            if last { break }
+        }
+
+        written
+    }
+
+    /// Writes the response, returning the number of bytes written. Nothing is written without a body.
+    /// Oversized responses are replaced with the response on error, or nothing if that is oversized too.
+    /// 
+    /// The tag echoes the request's ID ahead of the frame, if request IDs are set.
+    fn write_reply<W: Write>(cfg: &cfg::Config, conn: &Connection, tag: &str, body: Option<&str>, outcome: &mut Outcome, out: &mut W) -> io::Result<usize> {
+        const DEBUG_HANDLE: &str = "server::Host::write_reply";
+
+        let Some(body) = body else { return Ok(0) };
+
+        // Prepend length of message to response according to mrl.
+        let res = Host::prepend_length(body, cfg.mrl).unwrap_or_else(|_| {
+            cfg.debug.error(DEBUG_HANDLE, 
+                &format!("Response of {} bytes exceeds the max response length of {}!", body.len(), cfg.mrl),
+                &conn.fields());
+            *outcome = Outcome::Oversize;
+
+            Host::prepend_length(&cfg.er, cfg.mrl)
+                .or_else(|_| Host::prepend_length("", cfg.mrl))
+                .unwrap()
+        });
+
+        out.write_all(tag.as_bytes())?;
+        out.write_all(res.as_bytes())?;
+        Ok(tag.len() + res.len())
+    }
+
+    /// Writes each chunk as its own frame, followed by an empty frame marking the end of the stream, 
+    /// returning the number of bytes written. The writing half stays locked throughout, so pushed messages never land mid-stream.
+    /// 
+    /// An oversized chunk ends the stream early, replaced with the response on error if that fits.
+    /// 
+    /// The tag echoes the request's ID ahead of every frame, if request IDs are set.
+    fn write_stream<W: Write>(cfg: &cfg::Config, conn: &Connection, tag: &str, chunks: registerable::Chunks, outcome: &mut Outcome, out: &mut W) -> io::Result<usize> {
+        const DEBUG_HANDLE: &str = "server::Host::write_stream";
+
+        let mut written = 0;
+
+        for chunk in chunks.filter(|chunk| !chunk.is_empty()) {
+            let res = match Host::prepend_length(&chunk, cfg.mrl) {
+                Ok(res) => res,
+                Err(_) => {
+                    cfg.debug.error(DEBUG_HANDLE, 
+                        &format!("Chunk of {} bytes exceeds the max response length of {}!", chunk.len(), cfg.mrl),
+                        &conn.fields());
+                    *outcome = Outcome::Oversize;
+
+                    let res = Host::prepend_length(&cfg.er, cfg.mrl).unwrap_or_default();
+                    if !cfg.er.is_empty() { out.write_all(tag.as_bytes())?; out.write_all(res.as_bytes())?; written += tag.len() + res.len(); }
+                    break;
+                },
+            };
+
+            out.write_all(tag.as_bytes())?;
+            out.write_all(res.as_bytes())?;
+            written += tag.len() + res.len();
+        }
+
+        let end = Host::prepend_length("", cfg.mrl).unwrap();
+        out.write_all(tag.as_bytes())?;
+        out.write_all(end.as_bytes())?;
+
+        Ok(written + tag.len() + end.len())
+    }
+
+    /// Half-closes a connection bunker is ending, then waits for the client to close its side if set to.
+    /// Anything the client sends in the meantime is discarded.
+    fn close(cfg: &cfg::Config, stream: &mut TcpStream, conn: &Connection) {
+        const DEBUG_HANDLE: &str = "server::Host::close";
+
+        if !cfg.half_close { return }
+
+        if let Err(err) = stream.shutdown(Shutdown::Write) {
+            cfg.debug.warn(DEBUG_HANDLE, &format!("Failed to half-close connection: {}", err), &conn.fields());
+            return;
+        }
+
+        let Some(timeout) = cfg.close_ack else { return };
+        let deadline = Instant::now() + timeout;
+        let mut buff = [0_u8; 256];
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || stream.set_read_timeout(Some(remaining)).is_err() { break }
+
+            match stream.read(&mut buff) {
+                Ok(0) => {
+                    cfg.debug.debug(DEBUG_HANDLE, "Client acknowledged close.", &conn.fields());
+                    return;
+                },
+                Ok(_) => continue,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+
+        cfg.debug.debug(DEBUG_HANDLE, "Client did not acknowledge close.", &conn.fields());
+    }
+
+    /// Sends the goodbye response before a connection is closed for reaching one of its limits.
+    fn say_goodbye(cfg: &cfg::Config, conn: &Connection, limit: &str) {
+        const DEBUG_HANDLE: &str = "server::Host::say_goodbye";
+
+        cfg.debug.info(DEBUG_HANDLE, "Connection limit reached.", 
+            &[("ordern", &conn.ordern), ("peer", &conn.peer_str), ("limit", &limit)]);
+
+        let res = Host::prepend_length(&cfg.goodbye, cfg.mrl)
+            .or_else(|_| Host::prepend_length("", cfg.mrl))
+            .unwrap();
+
+        if let Err(err) = conn.out.write(res.as_bytes()) {
+            cfg.debug.warn(DEBUG_HANDLE, &format!("Failed to write goodbye: {}", err), &conn.fields());
+        }
+    }
+
+    /// Parses a single request, then passes it to the matching controller, returning the unframed response.
+    fn respond(cfg: &cfg::Config, conn: &Connection, data: &[u8]) -> Reply {
+        const DEBUG_HANDLE: &str = "server::Host::respond";
+
+        let req = String::from_utf8_lossy(data);
+        let req = req.trim(); // Removes whitespace for whitespace-sensitive parsing options.
+
+        if cfg.quit.as_ref().is_some_and(|quit| *quit == cfg.mp.apply(req)) {
+            cfg.debug.debug(DEBUG_HANDLE, "Client ending connection.", &conn.fields());
+            return Reply{ body: cfg.farewell.clone(), chunks: None, end: true, outcome: Outcome::Ok, matched: None };
+        }
+
+        let (path, msg) = Host::parse(cfg, req);
+
+        // Resolved before logging the request, so the route's redaction applies.
+        // The lock is released before serving, so the route map may change mid-request.
+        let found = if cfg.is_reserved(&path) { None } else { 
+            cfg.routes.read().unwrap().find(&path, req) 
+        };
+        let redaction = found.as_ref().map(|found| found.redaction.clone()).unwrap_or_default();
+
+        // Route written to the access log, which for reserved routes is the path itself.
+        let matched = match &found {
+            Some(found) => Some(found.route.clone()),
+            None if cfg.is_reserved(&path) => Some(path.clone()),
+            None => None,
+        };
+
+        if cfg.debug.enabled(Level::Trace) {
+            let shown = if redaction.is_plain() { Raw(data).to_string() } else { redaction.apply(req).into_owned() };
+
+            cfg.debug.trace(DEBUG_HANDLE, "Raw incoming data.", 
+                &[("ordern", &conn.ordern), ("peer", &conn.peer_str), ("size", &data.len()), ("data", &shown)]);
+            cfg.debug.trace(DEBUG_HANDLE, "Parsed message.", 
+                &[("ordern", &conn.ordern), ("peer", &conn.peer_str), ("path", &path), ("message", &redaction.apply(msg))]);
+        }
+
+        cfg.debug.debug(DEBUG_HANDLE, &format!("Received message: {}", redaction.apply(req)), &conn.fields());
+
+        // Reserved routes are exempt from rate limits, so probes are never turned away.
+        if !cfg.is_reserved(&path) && !cfg.limiter.check(conn.peer.map(|addr| addr.ip()), matched.as_ref()) {
+            let violations = conn.violations.fetch_add(1, Ordering::Relaxed) + 1;
+            let end = cfg.rl_drop.is_some_and(|max| violations >= max);
+
+            cfg.debug.warn(DEBUG_HANDLE, 
+                if end { "Rate limited, dropping connection." } else { "Rate limited." }, 
+                &[("ordern", &conn.ordern), ("peer", &conn.peer_str), ("violations", &violations)]);
+
+            return Reply{ body: Some(cfg.rl_response.clone()), chunks: None, end, outcome: Outcome::RateLimited, matched };
+        }
+
+        let error_b = Rc::new(RefCell::new(String::new()));
+
+        if let Some(found) = &found {
+            cfg.debug.trace(DEBUG_HANDLE, "Dispatching request.", 
+                &[("ordern", &conn.ordern), ("peer", &conn.peer_str), ("route", &found.route)]);
+        }
+
+        let res = Host::dispatch(cfg, &path, found, msg, conn.ordern, &error_b);
+
+        let error = error_b.take();
+        let end = res.is_closing();
+        let written = res.is_written();
+        let (mut res, chunks) = res.into_parts();
+
+        let outcome = match matched {
+            None => Outcome::InternalError,
+            Some(_) if !error.is_empty() => Outcome::ControllerError,
+            Some(_) => Outcome::Ok,
+        };
+
+        // Errors are always answered with the response on error, whether or not the controller replied.
+        if !error.is_empty() {
+            cfg.debug.error(DEBUG_HANDLE, &error, &conn.fields());
+            res = cfg.er.to_owned();
+        } else if !written {
+            cfg.debug.debug(DEBUG_HANDLE, 
+                if end { "Controller closing connection without reply." } else { "Controller not replying." }, 
+                &conn.fields());
+
+            return Reply{ body: None, chunks: None, end, outcome, matched };
+        }
+
+        if let Some(chunks) = chunks {
+            cfg.debug.debug(DEBUG_HANDLE, "Streaming response.", &conn.fields());
+            return Reply{ body: None, chunks: Some(chunks), end, outcome, matched };
+        }
+
+        // Legacy path, in which the controller replies with the end-connection message to close the connection.
+        let end = if !end && res == cfg.endconn_msg { 
+            cfg.debug.debug(
+                DEBUG_HANDLE, 
+                "Controller ending connection.",
+                &conn.fields()
+            );
+
+            match &cfg.farewell {
+                Some(farewell) => res = farewell.to_owned(),
+                None => return Reply{ body: None, chunks: None, end: true, outcome, matched },
+            }
+            true
+        } else { end };
+
+        cfg.debug.debug(DEBUG_HANDLE, &format!("Writing response: {}", redaction.apply(&res)), 
+            &[("ordern", &conn.ordern), ("peer", &conn.peer_str), ("size", &res.len())]);
+
+        Reply{ body: Some(res), chunks: None, end, outcome, matched }
+    }
+
+    /// Splits the ID off the front of the request if request IDs are set, returning it along with its separator as the tag echoed in the response.
+    /// Requests without a separator are answered with an empty ID.
+    fn split_id<'a>(cfg: &cfg::Config, data: &'a [u8]) -> (String, &'a [u8]) {
+        let Some(sep) = cfg.id_separator else { return (String::new(), data) };
+
+        let mut encoded = [0_u8; 4];
+        let sep = sep.encode_utf8(&mut encoded).as_bytes();
+
+        match data.windows(sep.len()).position(|window| window == sep) {
+            Some(pos) => (String::from_utf8_lossy(&data[..pos + sep.len()]).into_owned(), &data[pos + sep.len()..]),
+            None => (String::from_utf8_lossy(sep).into_owned(), data),
+        }
+    }
+
+    /// Splits the request into the path and the message, according to the parse options.
+    fn parse<'a>(cfg: &cfg::Config, req: &'a str) -> (Route, &'a str) {
+        match &cfg.parse_options {
+            // Requests too short to split, or split inside a character, match no path.
+            registerable::ParseOptions::Position(pos) => {
+                match (req.get(..*pos), req.get(*pos..)) {
+                    (Some(path), Some(msg)) => (Route::Path(cfg.mp.apply(path)), msg),
+                    _ => (Route::NotFound, req),
+                }
+            },
+            registerable::ParseOptions::Separators(chars) => {
+                match req.split_once(&chars[..]) {
+                    Some((path, msg)) => (Route::Path(cfg.mp.apply(path)), msg),
+                    None => (Route::NotFound, req),
+                }
+            },
+        }
+    }
+
+    /// Passes the message to the controller found for the path, unless the path is reserved by bunker.
+    fn dispatch(cfg: &cfg::Config, path: &Route, found: Option<cfg::Found>, msg: &str, ordern: u64, error_b: &Rc<RefCell<String>>) -> Response {
+        // Reserved routes handled by bunker bypass the route map.
+        if cfg.help.as_ref() == Some(path) { return Response::reply(Host::list_routes(cfg)); }
+        if cfg.health.as_ref() == Some(path) { return Response::reply(Host::status(cfg)); }
+        if cfg.subscribe.as_ref() == Some(path) || cfg.unsubscribe.as_ref() == Some(path) { 
+            return Host::manage_subscription(cfg, cfg.subscribe.as_ref() == Some(path), msg.trim(), ordern, error_b);
+        }
+
+        match found {
+            Some(cfg::Found{ controller, captures: None, .. }) => 
+                controller.respond(msg.to_string(), Rc::clone(error_b)),
+            Some(cfg::Found{ controller, captures: Some(captures), .. }) => 
+                controller.respond_captures(msg.to_string(), captures, Rc::clone(error_b)),
+            None => {
+                // Error results from the path not matching any key in the map.
+                let err = InternalError::NoControllerFound(ordern);
+                error_b.replace(err.to_string());
+                Response::reply(cfg.er.to_owned())
+            },
+        }
+    }
+
+    /// Subscribes or unsubscribes the connection for the subscribe and unsubscribe routes.
+    fn manage_subscription(cfg: &cfg::Config, subscribe: bool, topic: &str, ordern: u64, error_b: &Rc<RefCell<String>>) -> Response {
+        if topic.is_empty() {
+            error_b.replace(format!("Order Number {}'s Request did not name a topic!", ordern));
+            return Response::reply(cfg.er.to_owned());
+        }
+
+        let changed = if subscribe { cfg.topics.subscribe(topic, ordern) } else { cfg.topics.unsubscribe(topic, ordern) };
+
+        Response::reply(match (subscribe, changed) {
+            (true, true) => format!("Subscribed to {}.", topic),
+            (true, false) => format!("Already subscribed to {}.", topic),
+            (false, true) => format!("Unsubscribed from {}.", topic),
+            (false, false) => format!("Not subscribed to {}.", topic),
+        })
+    }
+
+    /// Describes the status of the server for the health route.
+    fn status(cfg: &cfg::Config) -> String {
+        let (busy, workers) = cfg.metrics.workers();
+        let utilization = if workers == 0 { 0.0 } else { busy as f64 / workers as f64 };
+
+        format!(
+            "status=ok uptime_s={} connections_active={} workers_busy={} workers={} utilization={:.2}",
+            cfg.metrics.uptime().as_secs(), cfg.metrics.active(), busy, workers, utilization
+        )
+    }
+
+    /// Lists every registered route, one per line, for the help route.
+    fn list_routes(cfg: &cfg::Config) -> String {
+        cfg.routes.read().unwrap().list()
+            .iter()
+            .map(|info| info.to_string())
+            .collect::<Vec<String>>()
+            .join("\n")
     }
 
     /// Prepends length of the message to the response, according to the given response length.
@@ -387,6 +1683,6 @@ impl Host {
         let mut out = String::new();
         for _ in 0..leading { out += "0"; }
 
-        Ok(out + &len_str + &message)
+        Ok(out + &len_str + message)
     }
 }
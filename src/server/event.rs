@@ -0,0 +1,320 @@
+//! Readiness-based engine for `Builder::event_loop`, serving every connection from a single thread.
+
+use std::{collections::{BTreeMap, BTreeSet, VecDeque}, io::{self, ErrorKind, Read, Write}, net::Shutdown, sync::{Arc, Condvar, Mutex, mpsc}, time::Instant};
+
+use mio::{Events, Interest, Poll, Token, Waker, net::{TcpListener, TcpStream}};
+
+use crate::{cfg, internal::{Command, Framer, Outbox, SharedStream}};
+
+use super::{Connection, Host, Pipeline};
+
+const LISTENER: Token = Token(0);
+const WAKER: Token = Token(usize::MAX);
+
+/// A connection owned by the event loop, registered under its order number.
+struct Client {
+    stream: TcpStream,
+    conn: Arc<Connection>,
+    pipeline: Arc<(Mutex<Pipeline>, Condvar)>,
+    framer: Option<Framer>,
+    /// Requests held back while the connection has as many tasks as it may. Nothing more is read until they are submitted.
+    backlog: VecDeque<Vec<u8>>,
+    /// Responses waiting for the stream to become writable.
+    outbound: Vec<u8>,
+    /// Set once the client closed its side.
+    eof: bool,
+    /// Set once writing to the client failed, after which anything written to it is discarded.
+    broken: bool,
+    /// Set once the max lifetime ended, after which nothing more is read. The goodbye waits for requests still in flight.
+    expired: bool,
+    /// Set once the connection is ending, with whether bunker is the one closing it.
+    closing: Option<bool>,
+    /// Set once half-closed, with when to stop waiting for the client to acknowledge.
+    ack: Option<Instant>
+}
+
+struct EventLoop<'a> {
+    host: &'a Host,
+    cfg: cfg::ConfigAlias,
+    poll: Poll,
+    listener: TcpListener,
+    waker: Arc<Waker>,
+    tx: mpsc::Sender<(u64, Command)>,
+    rx: mpsc::Receiver<(u64, Command)>,
+    clients: BTreeMap<u64, Client>,
+    buff: Vec<u8>
+}
+
+/// Accepts, reads and writes every connection on the calling thread until polling fails,
+/// submitting complete requests to the threadpool.
+pub(super) fn run(host: &Host, listener: std::net::TcpListener) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+
+    let poll = Poll::new()?;
+    let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+    let mut listener = TcpListener::from_std(listener);
+    poll.registry().register(&mut listener, LISTENER, Interest::READABLE)?;
+
+    let (tx, rx) = mpsc::channel();
+    let cfg = Arc::clone(&host.cfg);
+    let buff = vec![0_u8; cfg.read_buffer_size];
+
+    EventLoop{ host, cfg, poll, listener, waker, tx, rx, clients: BTreeMap::new(), buff }.run()
+}
+
+impl EventLoop<'_> {
+    fn run(mut self) -> io::Result<()> {
+        let mut events = Events::with_capacity(1024);
+
+        loop {
+            let timeout = self.next_deadline().map(|deadline| deadline.saturating_duration_since(Instant::now()));
+
+            if let Err(err) = self.poll.poll(&mut events, timeout) {
+                if err.kind() == ErrorKind::Interrupted { continue }
+                return Err(err);
+            }
+
+            // Connections to check on, once everything ready has been read.
+            let mut touched = BTreeSet::new();
+
+            for event in events.iter() {
+                match event.token() {
+                    LISTENER => self.accept(&mut touched),
+                    WAKER => (),
+                    Token(ordern) => {
+                        let ordern = ordern as u64;
+                        if event.is_readable() || event.is_read_closed() { self.read(ordern); }
+                        touched.insert(ordern);
+                    },
+                }
+            }
+
+            let now = Instant::now();
+            self.expire(now, &mut touched);
+
+            // Writes queued by the pool and pushed messages, in the order they were flushed.
+            while let Ok((ordern, command)) = self.rx.try_recv() {
+                let Some(client) = self.clients.get_mut(&ordern) else { continue }; // Already closed.
+                if let Command::Write(bytes) = command {
+                    if !client.broken { client.outbound.extend_from_slice(&bytes); }
+                }
+                touched.insert(ordern);
+            }
+
+            for ordern in touched { self.advance(ordern, now); }
+        }
+    }
+
+    /// Returns the earliest time a connection reaches its max lifetime or stops waiting for an acknowledgment.
+    fn next_deadline(&self) -> Option<Instant> {
+        if self.cfg.max_lifetime.is_none() && self.cfg.close_ack.is_none() { return None }
+
+        self.clients.values()
+            .filter_map(|client| client.ack.or_else(|| match (client.closing, client.expired) {
+                (None, false) => self.cfg.max_lifetime.map(|max| client.conn.opened + max),
+                _ => None,
+            }))
+            .min()
+    }
+
+    /// Accepts every pending connection permitted by the filter.
+    fn accept(&mut self, touched: &mut BTreeSet<u64>) {
+        const DEBUG_HANDLE: &str = "server::event::accept";
+
+        loop {
+            let (mut stream, peer) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.cfg.debug.warn(DEBUG_HANDLE, &format!("Failed to accept connection: {}", err), &[]);
+                    return;
+                },
+            };
+
+            let Some(ordern) = self.host.admit(Some(peer)) else { continue }; // Dropping the stream closes the connection.
+
+            if let Err(err) = self.poll.registry().register(&mut stream, Token(ordern as usize), Interest::READABLE | Interest::WRITABLE) {
+                self.cfg.debug.warn(DEBUG_HANDLE, &format!("Failed to register connection: {}", err), &[("ordern", &ordern)]);
+                continue;
+            }
+
+            // Writes from the pool and pushed messages are queued for the loop, which writes them once the stream is writable.
+            let out = SharedStream::queued(Outbox::new(ordern, self.tx.clone(), Arc::clone(&self.waker)));
+            self.cfg.registry.insert(ordern, out.clone());
+
+            let conn = Arc::new(Connection::new(ordern, Some(peer), out));
+
+            self.cfg.debug.info(DEBUG_HANDLE, "Connection initiated.", &conn.fields());
+            self.cfg.metrics.connection_accepted();
+
+            let framer = self.cfg.delimiter.as_ref().map(|delimiter| Framer::new(delimiter, self.cfg.read_buffer_size));
+            self.clients.insert(ordern, Client{
+                stream, conn, framer,
+                pipeline: Default::default(),
+                backlog: VecDeque::new(),
+                outbound: Vec::new(),
+                eof: false,
+                broken: false,
+                expired: false,
+                closing: None,
+                ack: None
+            });
+            touched.insert(ordern);
+        }
+    }
+
+    /// Reads everything the client sent, submitting the requests it completes, until requests are held back.
+    /// Anything read once the connection is closing is discarded.
+    fn read(&mut self, ordern: u64) {
+        const DEBUG_HANDLE: &str = "server::event::read";
+
+        let Some(client) = self.clients.get_mut(&ordern) else { return };
+
+        while !client.eof && client.backlog.is_empty() {
+            let size = match client.stream.read(&mut self.buff) {
+                Ok(0) => { client.eof = true; break }, // Client ended the connection.
+                Ok(size) => size,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.cfg.debug.warn(DEBUG_HANDLE, &format!("Failed to read request: {}", err), &client.conn.fields());
+                    client.eof = true;
+                    break;
+                },
+            };
+
+            if client.closing.is_some() || client.expired { continue }
+
+            // Without a delimiter, every read is a single request.
+            match &mut client.framer {
+                Some(framer) => client.backlog.extend(framer.push(&self.buff[0..size])),
+                None => client.backlog.push_back(self.buff[0..size].to_vec()),
+            }
+
+            dispatch(&self.cfg, client);
+        }
+    }
+
+    /// Sends the goodbye response to every connection that reached its max lifetime, 
+    /// once the requests being served when it ended are answered.
+    fn expire(&mut self, now: Instant, touched: &mut BTreeSet<u64>) {
+        for (ordern, client) in self.clients.iter_mut() {
+            if client.ack.is_some_and(|deadline| now >= deadline) { touched.insert(*ordern); }
+
+            if client.closing.is_some() || self.cfg.max_lifetime.is_none_or(|max| now.duration_since(client.conn.opened) < max) { continue }
+            client.expired = true;
+
+            // Checked again once the last task in flight wakes the loop.
+            let mut state = client.pipeline.0.lock().unwrap();
+            if state.in_flight > 0 { continue }
+
+            if state.closed.is_none() {
+                Host::say_goodbye(&self.cfg, &client.conn, "max_lifetime");
+                state.closed = Some(true);
+            }
+            touched.insert(*ordern);
+        }
+    }
+
+    /// Writes whatever is queued for the connection, then closes it once nothing is left in flight or to write.
+    fn advance(&mut self, ordern: u64, now: Instant) {
+        const DEBUG_HANDLE: &str = "server::event::advance";
+
+        // Reading resumes once every request held back is submitted.
+        let cfg = &self.cfg;
+        if self.clients.get_mut(&ordern).is_some_and(|client| dispatch(cfg, client)) { self.read(ordern); }
+
+        let Some(client) = self.clients.get_mut(&ordern) else { return };
+
+        if let Err(err) = flush(client) {
+            self.cfg.debug.warn(DEBUG_HANDLE, &format!("Failed to write response: {}", err), &client.conn.fields());
+            client.broken = true;
+            client.outbound.clear();
+            client.pipeline.0.lock().unwrap().closed.get_or_insert(false);
+        }
+
+        // Already half-closed, waiting for the client to acknowledge.
+        if let Some(deadline) = client.ack {
+            if client.eof {
+                self.cfg.debug.debug(DEBUG_HANDLE, "Client acknowledged close.", &client.conn.fields());
+            } else if now >= deadline {
+                self.cfg.debug.debug(DEBUG_HANDLE, "Client did not acknowledge close.", &client.conn.fields());
+            } else { return }
+
+            return self.end(ordern);
+        }
+
+        if client.closing.is_none() {
+            // Requests already submitted are answered, unless bunker is closing the connection, before it ends.
+            let state = client.pipeline.0.lock().unwrap();
+            if state.in_flight > 0 { return }
+
+            client.closing = state.closed.or(if client.eof || client.broken { Some(false) } else { None });
+            drop(state);
+
+            if client.closing.is_none() { return }
+
+            // Unregistered first, so nothing is pushed after the last response.
+            self.cfg.registry.remove(ordern);
+            self.cfg.topics.remove(ordern);
+        }
+
+        if !client.outbound.is_empty() { return }
+
+        if client.closing == Some(true) && self.cfg.half_close && !client.broken {
+            if let Err(err) = client.stream.shutdown(Shutdown::Write) {
+                self.cfg.debug.warn(DEBUG_HANDLE, &format!("Failed to half-close connection: {}", err), &client.conn.fields());
+            } else if let Some(timeout) = self.cfg.close_ack {
+                client.ack = Some(now + timeout);
+                if !client.eof { return }
+
+                self.cfg.debug.debug(DEBUG_HANDLE, "Client acknowledged close.", &client.conn.fields());
+            }
+        }
+
+        self.end(ordern);
+    }
+
+    /// Drops a connection that has closed.
+    fn end(&mut self, ordern: u64) {
+        const DEBUG_HANDLE: &str = "server::event::end";
+
+        let Some(mut client) = self.clients.remove(&ordern) else { return };
+        let _ = self.poll.registry().deregister(&mut client.stream);
+
+        self.cfg.debug.info(DEBUG_HANDLE, "Closing connection.", &client.conn.fields());
+        self.cfg.metrics.connection_closed();
+    }
+}
+
+/// Submits the requests held back while the connection has fewer tasks than it may, or discards them once it is closing.
+/// Returns whether that emptied the backlog.
+fn dispatch(cfg: &cfg::ConfigAlias, client: &mut Client) -> bool {
+    if client.backlog.is_empty() { return false }
+
+    let mut state = client.pipeline.0.lock().unwrap();
+    if state.closed.is_some() { client.backlog.clear(); }
+
+    while state.in_flight < cfg.max_in_flight {
+        let Some(req) = client.backlog.pop_front() else { break };
+        Host::submit(cfg, &client.conn, &client.pipeline, &mut state, req);
+    }
+
+    client.backlog.is_empty()
+}
+
+/// Writes as much of the queued responses as the stream accepts without blocking.
+fn flush(client: &mut Client) -> io::Result<()> {
+    while !client.outbound.is_empty() {
+        match client.stream.write(&client.outbound) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(size) => { client.outbound.drain(..size); },
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}